@@ -0,0 +1,114 @@
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::usage::{self, LedgerEntry};
+
+// Spending caps, checked against the usage ledger before any request is sent.
+// Only requests to paid providers count towards them.
+
+/// Stored under `budget` in `config.json`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BudgetSettings {
+    #[serde(default)]
+    pub daily: Limits,
+    #[serde(default)]
+    pub monthly: Limits,
+    /// Local Ollama model to use instead of paid providers once a hard limit is reached.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback_ollama_model: Option<String>,
+}
+
+impl BudgetSettings {
+    /// Whether a hard dollar limit is set for either period.
+    pub fn has_hard_usd_limit(&self) -> bool {
+        self.daily.hard_usd.is_some() || self.monthly.hard_usd.is_some()
+    }
+}
+
+/// Limits for one period. Cost is only known for providers that publish
+/// prices, so token limits are available as well; unset limits don't apply.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Limits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub soft_usd: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hard_usd: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub soft_tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hard_tokens: Option<u64>,
+}
+
+pub enum Status {
+    WithinBudget,
+    /// Past a soft limit: warn, but send the request.
+    OverSoftLimit(String),
+    /// Past a hard limit: paid providers must not be called.
+    OverHardLimit(String),
+}
+
+#[derive(Default)]
+struct Spent {
+    /// Dollars spent on requests whose cost is known.
+    usd: f64,
+    /// Requests whose cost is unknown, which `usd` can't include.
+    unpriced: u64,
+    tokens: u64,
+}
+
+impl Spent {
+    fn add(&mut self, entry: &LedgerEntry) {
+        match entry.cost_usd {
+            Some(cost) => self.usd += cost,
+            None => self.unpriced += 1,
+        }
+        self.tokens += entry.usage.total_tokens;
+    }
+}
+
+/// Compares today's and this month's spending (UTC) with the limits. Hard
+/// limits take precedence over soft ones.
+pub fn check(settings: &BudgetSettings) -> Result<Status, Box<dyn std::error::Error>> {
+    let today = usage::utc_date(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs());
+    let this_month = &today[..7];
+
+    let mut daily = Spent::default();
+    let mut monthly = Spent::default();
+    for entry in usage::load_ledger()?.iter().filter(|entry| !entry.free) {
+        let date = usage::utc_date(entry.timestamp);
+        if date == today {
+            daily.add(entry);
+        }
+        if date.starts_with(this_month) {
+            monthly.add(entry);
+        }
+    }
+
+    let periods = [("daily", &settings.daily, &daily), ("monthly", &settings.monthly, &monthly)];
+
+    for (period, limits, spent) in periods {
+        if let Some(reason) = exceeded(period, "hard", limits.hard_usd, limits.hard_tokens, spent) {
+            return Ok(Status::OverHardLimit(reason));
+        }
+    }
+    for (period, limits, spent) in periods {
+        if let Some(reason) = exceeded(period, "soft", limits.soft_usd, limits.soft_tokens, spent) {
+            return Ok(Status::OverSoftLimit(reason));
+        }
+    }
+    Ok(Status::WithinBudget)
+}
+
+fn exceeded(period: &str, kind: &str, usd: Option<f64>, tokens: Option<u64>, spent: &Spent) -> Option<String> {
+    if let Some(limit) = usd.filter(|limit| spent.usd >= *limit) {
+        let unpriced = match spent.unpriced {
+            0 => String::new(),
+            1 => " (not counting 1 request of unknown cost)".to_string(),
+            count => format!(" (not counting {} requests of unknown cost)", count),
+        };
+        return Some(format!("{} spending of ${:.2}{} has reached the {} limit of ${:.2}", period, spent.usd, unpriced, kind, limit));
+    }
+    if let Some(limit) = tokens.filter(|limit| spent.tokens >= *limit) {
+        return Some(format!("{} usage of {} tokens has reached the {} limit of {}", period, spent.tokens, kind, limit));
+    }
+    None
+}
@@ -0,0 +1,60 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use crate::escalation::Escalation;
+use crate::model::Model;
+
+/// A cached command, keyed by prompt in `cache.json`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum CacheEntry {
+    /// Entries written before the cache recorded where commands came from.
+    Command(String),
+    Generated {
+        command: String,
+        model: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        escalation: Option<Escalation>,
+    },
+}
+
+impl CacheEntry {
+    pub fn command(&self) -> &str {
+        match self {
+            CacheEntry::Command(command) | CacheEntry::Generated { command, .. } => command,
+        }
+    }
+
+    /// Whether one of `models` generated the command, directly or by escalating
+    /// to another model. Old entries don't say, so they count as a match.
+    pub fn is_from_any(&self, models: &[Model]) -> bool {
+        match self {
+            CacheEntry::Command(_) => true,
+            CacheEntry::Generated { model, escalation, .. } => {
+                let origin = escalation.as_ref().map_or(model, |escalation| &escalation.from);
+                models.iter().any(|candidate| candidate.to_string() == *origin)
+            }
+        }
+    }
+}
+
+pub fn get_cache_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let exe_path = std::env::current_exe()?;
+    let exe_dir = exe_path.parent().ok_or("Failed to get executable directory")?;
+    Ok(exe_dir.join("cache.json"))
+}
+
+pub fn load_cache(path: &PathBuf) -> Result<HashMap<String, CacheEntry>, Box<dyn std::error::Error>> {
+    if let Ok(content) = fs::read_to_string(path) {
+        Ok(serde_json::from_str(&content)?)
+    } else {
+        Ok(HashMap::new())
+    }
+}
+
+pub fn save_cache(path: &PathBuf, cache: &HashMap<String, CacheEntry>) -> Result<(), Box<dyn std::error::Error>> {
+    let content = serde_json::to_string_pretty(&cache)?;
+    fs::write(path, content)?;
+    Ok(())
+}
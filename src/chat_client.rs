@@ -0,0 +1,261 @@
+use reqwest::blocking::{Client, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use crate::http;
+use crate::sse;
+use crate::usage::Usage;
+
+// Typed subset of the OpenAI chat completions API, shared by every backend
+// that speaks it (OpenAI, OpenRouter, Ollama, Azure and compatible servers).
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn system(content: &str) -> Self {
+        ChatMessage { role: "system".to_string(), content: content.to_string() }
+    }
+
+    pub fn user(content: &str) -> Self {
+        ChatMessage { role: "user".to_string(), content: content.to_string() }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ChatCompletionRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<i32>,
+    /// Replaces `max_tokens` for OpenAI's reasoning models, and also covers their reasoning tokens.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_completion_tokens: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Backend-specific fields, sent at the top level of the body.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ChatCompletionResponse {
+    pub id: Option<String>,
+    /// The model that answered, which can differ from the one requested.
+    pub model: Option<String>,
+    #[serde(default)]
+    pub choices: Vec<ChatChoice>,
+    pub usage: Option<Usage>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ChatChoice {
+    pub message: Option<ResponseMessage>,
+    pub logprobs: Option<ChoiceLogprobs>,
+}
+
+impl ChatChoice {
+    /// Confidence derived from the token log probabilities, if they were requested and returned.
+    pub fn confidence(&self) -> Option<f64> {
+        let tokens = self.logprobs.as_ref()?.content.as_ref()?;
+        confidence(&tokens.iter().map(|token| token.logprob).collect::<Vec<_>>())
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ChoiceLogprobs {
+    pub content: Option<Vec<TokenLogprob>>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TokenLogprob {
+    pub logprob: f64,
+}
+
+/// Geometric mean of the token probabilities: 1.0 when the model was certain
+/// of every token, lower the more it hesitated.
+pub fn confidence(logprobs: &[f64]) -> Option<f64> {
+    if logprobs.is_empty() {
+        return None;
+    }
+    Some((logprobs.iter().sum::<f64>() / logprobs.len() as f64).exp())
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ResponseMessage {
+    pub content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChatCompletionChunk {
+    id: Option<String>,
+    model: Option<String>,
+    #[serde(default)]
+    choices: Vec<ChunkChoice>,
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct ChunkChoice {
+    delta: Option<ChunkDelta>,
+    logprobs: Option<ChoiceLogprobs>,
+}
+
+#[derive(Deserialize)]
+struct ChunkDelta {
+    content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ModelsResponse {
+    data: Vec<ModelEntry>,
+}
+
+#[derive(Deserialize)]
+struct ModelEntry {
+    id: String,
+}
+
+/// Everything a streamed completion added up to.
+pub struct StreamedCompletion {
+    pub id: Option<String>,
+    pub model: Option<String>,
+    pub text: String,
+    pub confidence: Option<f64>,
+    /// Only sent when `stream_options.include_usage` was requested.
+    pub usage: Option<Usage>,
+}
+
+/// A chat completions endpoint together with the headers and query
+/// parameters every request to it needs.
+pub struct ChatClient {
+    http: Client,
+    base_url: String,
+    headers: Vec<(String, String)>,
+    query: Vec<(String, String)>,
+}
+
+impl ChatClient {
+    pub fn new(base_url: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(ChatClient {
+            http: http::client()?,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            headers: Vec::new(),
+            query: Vec::new(),
+        })
+    }
+
+    pub fn bearer_auth(self, api_key: &str) -> Self {
+        self.header("Authorization", &format!("Bearer {}", api_key))
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn query(mut self, name: &str, value: &str) -> Self {
+        self.query.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    fn prepare(&self, request: RequestBuilder) -> RequestBuilder {
+        let request = self.headers.iter()
+            .fold(request, |request, (name, value)| request.header(name, value));
+        if self.query.is_empty() { request } else { request.query(&self.query) }
+    }
+
+    pub fn chat_completion(&self, body: &ChatCompletionRequest) -> Result<ChatCompletionResponse, Box<dyn std::error::Error>> {
+        let url = format!("{}/chat/completions", self.base_url);
+        let response = http::send_with_retry(&format!("API Error for model {}", body.model), || {
+            self.prepare(self.http.post(&url)).json(body)
+        })?;
+
+        Ok(response.json()?)
+    }
+
+    /// Streams a completion, calling `on_token` with each piece of content as
+    /// it arrives, and returns the full text.
+    pub fn chat_completion_stream(
+        &self,
+        body: &ChatCompletionRequest,
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<StreamedCompletion, Box<dyn std::error::Error>> {
+        let body = ChatCompletionRequest { stream: Some(true), ..body.clone() };
+        let url = format!("{}/chat/completions", self.base_url);
+        let response = http::send_with_retry(&format!("API Error for model {}", body.model), || {
+            self.prepare(self.http.post(&url)).json(&body)
+        })?;
+
+        let mut text = String::new();
+        let mut logprobs = Vec::new();
+        let mut usage = None;
+        let mut id = None;
+        let mut model = None;
+        sse::read_events(response, |data| {
+            if data == "[DONE]" {
+                return Ok(false);
+            }
+            let chunk: ChatCompletionChunk = serde_json::from_str(data)
+                .map_err(|e| format!("Unexpected stream chunk from model {}: {} ({})", body.model, data, e))?;
+            // With `include_usage`, the counts arrive in a final chunk without choices.
+            if chunk.usage.is_some() {
+                usage = chunk.usage;
+            }
+            if id.is_none() {
+                id = chunk.id;
+            }
+            if model.is_none() {
+                model = chunk.model;
+            }
+            for choice in chunk.choices {
+                if let Some(tokens) = choice.logprobs.and_then(|logprobs| logprobs.content) {
+                    logprobs.extend(tokens.iter().map(|token| token.logprob));
+                }
+                if let Some(content) = choice.delta.and_then(|delta| delta.content) {
+                    on_token(&content);
+                    text.push_str(&content);
+                }
+            }
+            Ok(true)
+        })?;
+
+        Ok(StreamedCompletion { id, model, text, confidence: confidence(&logprobs), usage })
+    }
+
+    pub fn list_models(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let url = format!("{}/models", self.base_url);
+        let response = http::send_with_retry(&format!("Failed to list models from {}", self.base_url), || {
+            self.prepare(self.http.get(&url))
+        })?;
+
+        let models: ModelsResponse = response.json()?;
+        Ok(models.data.into_iter().map(|model| model.id).collect())
+    }
+}
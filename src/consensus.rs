@@ -0,0 +1,238 @@
+use colored::*;
+use std::thread;
+use crate::fallback;
+use crate::interrupt::{Cancelled, RequestGuard};
+use crate::model::{Model, Suggestion};
+use crate::Config;
+
+// Consensus mode: the same prompt goes to several models at once, and a
+// command is only presented as agreed on when every model that answered
+// produced it. Otherwise the answers are shown side by side with their
+// differences highlighted, and the user picks one.
+
+/// A shell word, both as the shell sees it and as it was written.
+struct Word {
+    text: String,
+    raw: String,
+}
+
+/// Answers that are the same command once normalised, and the models that gave them.
+struct Group {
+    words: Vec<Word>,
+    suggestion: Suggestion,
+    models: Vec<Model>,
+}
+
+impl Group {
+    fn normalized(&self) -> Vec<&str> {
+        self.words.iter().map(|word| word.text.as_str()).collect()
+    }
+
+    fn model_names(&self) -> String {
+        self.models.iter().map(|model| model.to_string()).collect::<Vec<_>>().join(", ")
+    }
+}
+
+/// Asks every model in parallel and settles on one command, either because
+/// they agree or because the user chose it. Returns `None` if no model had a
+/// usable answer.
+pub fn run(models: &[Model], config: &Config, prompt: &str) -> Result<Option<(Model, Suggestion)>, Box<dyn std::error::Error>> {
+    if models.len() < 2 {
+        return Err("--consensus needs at least two models under `consensus_models` in config.json".into());
+    }
+
+    let guard = RequestGuard::new();
+    let answers: Vec<(Model, Result<Option<Suggestion>, fallback::SendableError>)> = thread::scope(|scope| {
+        let handles: Vec<_> = models.iter()
+            .map(|model| (model, scope.spawn(|| model.llm_get_command(config, prompt).map_err(fallback::sendable))))
+            .collect();
+        handles.into_iter()
+            .map(|(model, handle)| {
+                let answer = handle.join().unwrap_or_else(|_| Err("request thread panicked".into()));
+                (model.clone(), answer)
+            })
+            .collect()
+    });
+    // Ctrl-C exits as usual again while the user picks an answer.
+    drop(guard);
+
+    let mut groups: Vec<Group> = Vec::new();
+    let mut first_error = None;
+    for (model, answer) in answers {
+        match answer {
+            Ok(Some(suggestion)) => {
+                let words = shell_words(&suggestion.command);
+                let normalized: Vec<&str> = words.iter().map(|word| word.text.as_str()).collect();
+                match groups.iter_mut().find(|group| group.normalized() == normalized) {
+                    Some(group) => group.models.push(model),
+                    None => groups.push(Group { words, suggestion, models: vec![model] }),
+                }
+            }
+            Ok(None) => eprintln!("{}", format!("{} had no answer.", model).yellow()),
+            Err(e) => {
+                eprintln!("{}", format!("{} failed: {}", model, e).yellow());
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+
+    if groups.len() <= 1 {
+        let Some(group) = groups.pop() else {
+            // With no answer at all, the caller handles the first failure as it would for one model.
+            return match first_error {
+                Some(e) => Err(e),
+                None => Ok(None),
+            };
+        };
+        if group.models.len() > 1 {
+            println!("{}", format!("✔ consensus: {} agree", group.model_names()).green().bold());
+        } else {
+            println!("{}", format!("No consensus: only {} answered.", group.model_names()).yellow());
+        }
+        let model = group.models[0].clone();
+        return Ok(Some((model, group.suggestion)));
+    }
+
+    print_side_by_side(&groups);
+    match crate::read_choice(groups.len())? {
+        Some(index) => {
+            let group = groups.swap_remove(index);
+            let model = group.models[0].clone();
+            Ok(Some((model, group.suggestion)))
+        }
+        None => Err(Cancelled.into()),
+    }
+}
+
+/// Lists the differing answers in two columns, models and command, with the
+/// words that not every answer shares highlighted.
+fn print_side_by_side(groups: &[Group]) {
+    println!("{}", "The models disagree:".yellow().bold());
+
+    let labels: Vec<String> = groups.iter().map(Group::model_names).collect();
+    let width = labels.iter().map(|label| label.chars().count()).max().unwrap_or(0);
+
+    for (idx, (group, label)) in groups.iter().zip(&labels).enumerate() {
+        let mut shared = vec![true; group.words.len()];
+        for (other_idx, other) in groups.iter().enumerate() {
+            if other_idx != idx {
+                let in_common = common_words(&group.normalized(), &other.normalized());
+                shared.iter_mut().zip(in_common).for_each(|(shared, in_common)| *shared &= in_common);
+            }
+        }
+
+        let command: Vec<String> = group.words.iter().zip(shared)
+            .map(|(word, shared)| if shared { word.raw.cyan().to_string() } else { word.raw.yellow().bold().to_string() })
+            .collect();
+        println!(" {}. {:<width$} │ {}", idx + 1, label, command.join(" "), width = width);
+    }
+}
+
+/// Marks the words of `a` that are part of a longest common subsequence with `b`.
+fn common_words(a: &[&str], b: &[&str]) -> Vec<bool> {
+    let mut lengths = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i][j] = if a[i] == b[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut in_common = vec![false; a.len()];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            in_common[i] = true;
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    in_common
+}
+
+/// Splits a command into shell words with quotes and escapes resolved, so
+/// `-name '*.rs'`, `-name "*.rs"` and `-name  *.rs` compare equal.
+fn shell_words(command: &str) -> Vec<Word> {
+    let mut words = Vec::new();
+    let mut text = String::new();
+    let mut start: Option<usize> = None;
+    let mut quote: Option<char> = None;
+
+    let mut chars = command.char_indices();
+    while let Some((idx, c)) = chars.next() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            // Inside double quotes a backslash only escapes these; before
+            // anything else it is an ordinary character.
+            Some('"') if c == '\\' => match chars.clone().next() {
+                Some((_, next @ ('$' | '`' | '"' | '\\' | '\n'))) => {
+                    chars.next();
+                    text.push(next);
+                }
+                _ => text.push(c),
+            },
+            Some(_) => text.push(c),
+            None if c.is_whitespace() => {
+                if let Some(word_start) = start.take() {
+                    words.push(Word { text: std::mem::take(&mut text), raw: command[word_start..idx].to_string() });
+                }
+            }
+            None => {
+                start.get_or_insert(idx);
+                match c {
+                    '\'' | '"' => quote = Some(c),
+                    '\\' => {
+                        if let Some((_, next)) = chars.next() {
+                            text.push(next);
+                        }
+                    }
+                    _ => text.push(c),
+                }
+            }
+        }
+    }
+    if let Some(word_start) = start {
+        words.push(Word { text, raw: command[word_start..].to_string() });
+    }
+
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::shell_words;
+
+    #[test]
+    fn splits_commands_into_shell_words() {
+        let cases: &[(&str, &[&str])] = &[
+            ("find . -name '*.rs'", &["find", ".", "-name", "*.rs"]),
+            ("find . -name \"*.rs\"", &["find", ".", "-name", "*.rs"]),
+            ("find  .\t-name *.rs ", &["find", ".", "-name", "*.rs"]),
+            ("echo 'it'\\''s'", &["echo", "it's"]),
+            ("echo \"a \\\"b\\\" \\$HOME\"", &["echo", "a \"b\" $HOME"]),
+            ("grep \"a\\d\" f", &["grep", "a\\d", "f"]),
+            ("grep 'a\\d' f", &["grep", "a\\d", "f"]),
+            ("touch my\\ file", &["touch", "my file"]),
+            ("printf ''", &["printf", ""]),
+            ("ls -la", &["ls", "-la"]),
+            ("", &[]),
+        ];
+        for (command, expected) in cases {
+            let words: Vec<String> = shell_words(command).into_iter().map(|word| word.text).collect();
+            assert_eq!(words, *expected, "{}", command);
+        }
+    }
+
+    #[test]
+    fn keeps_each_word_as_written() {
+        let raw: Vec<String> = shell_words("find  . -name \"*.rs\"").into_iter().map(|word| word.raw).collect();
+        assert_eq!(raw, ["find", ".", "-name", "\"*.rs\""]);
+    }
+}
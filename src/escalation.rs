@@ -0,0 +1,77 @@
+use colored::*;
+use serde::{Deserialize, Serialize};
+use crate::model::{Model, Suggestion};
+use crate::sanitize::UnusableOutput;
+
+// Escalation: when a cheap model gives up, answers with something we won't
+// run, or wasn't sure of its answer, the same prompt is retried once on a
+// stronger model before giving up.
+
+/// Stored under `escalation` in `config.json`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EscalationSettings {
+    /// The stronger model to retry on.
+    pub model: Model,
+    /// Answers whose token-probability confidence is below this (0 to 1) are
+    /// retried. Only backends that return logprobs can be judged this way.
+    #[serde(default = "default_min_confidence")]
+    pub min_confidence: f64,
+}
+
+fn default_min_confidence() -> f64 {
+    0.7
+}
+
+/// Which model an answer was escalated from and why, kept in the cache entry.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Escalation {
+    pub from: String,
+    pub reason: String,
+}
+
+pub type Outcome = Result<Option<(Model, Vec<Suggestion>)>, Box<dyn std::error::Error>>;
+
+/// Why the first model's outcome isn't good enough, or `None` to keep it.
+/// Errors other than unusable output are left to the fallback chain.
+fn reason_to_escalate(outcome: &Outcome, min_confidence: f64) -> Option<String> {
+    match outcome {
+        Ok(None) => Some("empty answer".to_string()),
+        Ok(Some((_, suggestions))) => {
+            let confidences: Option<Vec<f64>> = suggestions.iter().map(|suggestion| suggestion.confidence).collect();
+            let best = confidences?.into_iter().fold(f64::NAN, f64::max);
+            (best < min_confidence).then(|| format!("low confidence ({:.0}%)", best * 100.0))
+        }
+        Err(e) => e.downcast_ref::<UnusableOutput>().map(|unusable| format!("unusable answer: {}", unusable.reason)),
+    }
+}
+
+/// Retries on the stronger model if `outcome`, produced by `tried`, calls for
+/// it, replacing `outcome` with the stronger model's answer. If that model has
+/// no answer either, the original outcome is kept.
+pub fn escalate(
+    settings: &EscalationSettings,
+    tried: &Model,
+    outcome: &mut Outcome,
+    ask: impl FnOnce(&Model) -> Result<Vec<Suggestion>, Box<dyn std::error::Error>>,
+) -> Option<Escalation> {
+    let reason = reason_to_escalate(outcome, settings.min_confidence)?;
+    if tried.to_string() == settings.model.to_string() {
+        return None;
+    }
+
+    eprintln!("{}", format!("{}: {}. Escalating to {}.", tried, reason, settings.model).yellow());
+    match ask(&settings.model) {
+        Ok(suggestions) if !suggestions.is_empty() => {
+            *outcome = Ok(Some((settings.model.clone(), suggestions)));
+            Some(Escalation { from: tried.to_string(), reason })
+        }
+        Ok(_) => {
+            eprintln!("{}", format!("{} had no answer either.", settings.model).yellow());
+            None
+        }
+        Err(e) => {
+            eprintln!("{}", format!("Escalation to {} failed: {}", settings.model, e).yellow());
+            None
+        }
+    }
+}
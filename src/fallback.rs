@@ -0,0 +1,88 @@
+use colored::*;
+use crate::http::{HttpError, HttpErrorKind};
+use crate::interrupt::Cancelled;
+use crate::model::Model;
+use crate::provider::MissingCredential;
+use crate::sanitize::UnusableOutput;
+
+// Fallback chain: the models in `config.json` are tried in order, and a model
+// that can't be reached, rejects our credentials or answers with nothing hands
+// over to the next one. Any other error is final, since the next model would
+// most likely fail the same way.
+
+/// Whether an error means "this model is unavailable right now" rather than
+/// "this request is wrong".
+pub fn should_fall_back(error: &(dyn std::error::Error + 'static)) -> bool {
+    if error.is::<MissingCredential>() {
+        return true;
+    }
+    match error.downcast_ref::<HttpError>().map(|e| e.kind) {
+        Some(HttpErrorKind::Connect | HttpErrorKind::Timeout) => true,
+        Some(HttpErrorKind::Status(status)) => {
+            status.as_u16() == 401 || status.as_u16() == 403 || status.as_u16() == 429 || status.is_server_error()
+        }
+        _ => false,
+    }
+}
+
+/// An error that can be handed back from a request thread.
+pub type SendableError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Makes an error from a request thread sendable back to the caller. The
+/// types that callers look at (by `should_fall_back`, for Ctrl-C and for
+/// unusable output) are kept; any other error is reduced to its message.
+pub fn sendable(error: Box<dyn std::error::Error>) -> SendableError {
+    let error = match error.downcast::<HttpError>() {
+        Ok(error) => return error,
+        Err(error) => error,
+    };
+    let error = match error.downcast::<MissingCredential>() {
+        Ok(error) => return error,
+        Err(error) => error,
+    };
+    let error = match error.downcast::<Cancelled>() {
+        Ok(error) => return error,
+        Err(error) => error,
+    };
+    match error.downcast::<UnusableOutput>() {
+        Ok(error) => error,
+        Err(error) => error.to_string().into(),
+    }
+}
+
+/// Runs `attempt` against each model in turn until one produces a result,
+/// and returns it together with the model that produced it.
+///
+/// If every model fails, the last error is returned, or `Ok(None)` if the
+/// last model simply had nothing to say.
+pub fn first_success<T>(
+    models: &[Model],
+    mut attempt: impl FnMut(&Model) -> Result<Option<T>, Box<dyn std::error::Error>>,
+) -> Result<Option<(Model, T)>, Box<dyn std::error::Error>> {
+    let mut last_error = None;
+
+    for (idx, model) in models.iter().enumerate() {
+        let reason = match attempt(model) {
+            Ok(Some(value)) => return Ok(Some((model.clone(), value))),
+            Ok(None) => {
+                last_error = None;
+                "empty response".to_string()
+            }
+            Err(e) if should_fall_back(e.as_ref()) => {
+                let reason = e.to_string();
+                last_error = Some(e);
+                reason
+            }
+            Err(e) => return Err(e),
+        };
+
+        if let Some(next) = models.get(idx + 1) {
+            eprintln!("{}", format!("{} failed ({}). Falling back to {}.", model, reason, next).yellow());
+        }
+    }
+
+    match last_error {
+        Some(e) => Err(e),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,189 @@
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::OnceLock;
+use std::time::Duration;
+use crate::interrupt;
+
+// Shared HTTP plumbing for every backend: one client configuration with
+// timeouts, and a retry loop with exponential backoff for rate limits,
+// overloaded servers and dropped connections.
+
+/// Timeouts and retry policy, stored under `http` in `config.json`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct HttpSettings {
+    /// Seconds to wait for a TCP/TLS connection.
+    pub connect_timeout_secs: u64,
+    /// Seconds to wait for the response, or between chunks when streaming.
+    pub read_timeout_secs: u64,
+    /// Retries after the first attempt; 0 disables retrying.
+    pub max_retries: u32,
+    /// Backoff before the first retry, doubled on each further retry.
+    pub initial_backoff_ms: u64,
+    /// Upper bound for a single backoff, including `Retry-After`.
+    pub max_backoff_ms: u64,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        HttpSettings {
+            connect_timeout_secs: 10,
+            read_timeout_secs: 120,
+            max_retries: 3,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+        }
+    }
+}
+
+static SETTINGS: OnceLock<HttpSettings> = OnceLock::new();
+
+/// Applies the settings from the config file. Only the first call has any
+/// effect; until then the defaults are used.
+pub fn configure(settings: HttpSettings) {
+    let _ = SETTINGS.set(settings);
+}
+
+fn settings() -> &'static HttpSettings {
+    SETTINGS.get_or_init(HttpSettings::default)
+}
+
+/// A blocking client with the configured timeouts.
+pub fn client() -> Result<Client, Box<dyn std::error::Error>> {
+    let settings = settings();
+    Ok(Client::builder()
+        .user_agent(format!("llm-term/{}", env!("CARGO_PKG_VERSION")))
+        .connect_timeout(Duration::from_secs(settings.connect_timeout_secs))
+        .timeout(Duration::from_secs(settings.read_timeout_secs))
+        .build()?)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpErrorKind {
+    Connect,
+    Timeout,
+    Status(StatusCode),
+    /// Stopped by Ctrl-C before a response arrived.
+    Cancelled,
+    Other,
+}
+
+/// A request that failed for good, with the transient failures that were retried before it.
+#[derive(Debug)]
+pub struct HttpError {
+    pub context: String,
+    pub kind: HttpErrorKind,
+    pub detail: String,
+    pub retried: Vec<String>,
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            HttpErrorKind::Status(status) => write!(f, "{}: {} - {}", self.context, status, self.detail)?,
+            _ => write!(f, "{}: {}", self.context, self.detail)?,
+        }
+        if !self.retried.is_empty() {
+            write!(f, " (retried {} time(s) after: {})", self.retried.len(), self.retried.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+/// Sends the request built by `build`, retrying rate limits (429), server
+/// errors (5xx), timeouts and connection failures with exponential backoff and
+/// jitter, or after the delay the server asks for in `Retry-After`. Returns
+/// the response only if it was successful, or 304 Not Modified for
+/// conditional requests; any other outcome becomes an `HttpError` described
+/// by `context`. Ctrl-C stops it while the request is sent and between
+/// retries.
+pub fn send_with_retry(context: &str, build: impl Fn() -> RequestBuilder) -> Result<Response, HttpError> {
+    let settings = settings();
+    let mut retried = Vec::new();
+    let cancelled = |retried| HttpError {
+        context: context.to_string(),
+        kind: HttpErrorKind::Cancelled,
+        detail: interrupt::Cancelled.to_string(),
+        retried,
+    };
+
+    for attempt in 0..=settings.max_retries {
+        let last_attempt = attempt == settings.max_retries;
+
+        let request = build();
+        let Ok(sent) = interrupt::run(move || request.send()) else {
+            return Err(cancelled(retried));
+        };
+        let (kind, detail, retry_after) = match sent {
+            Ok(response) if response.status().is_success() || response.status() == StatusCode::NOT_MODIFIED => return Ok(response),
+            Ok(response) => {
+                let status = response.status();
+                let retry_after = retry_after(&response);
+                let body = response.text().unwrap_or_else(|_| "No error body".to_string());
+                (HttpErrorKind::Status(status), body, retry_after)
+            }
+            Err(e) if e.is_timeout() => (HttpErrorKind::Timeout, e.to_string(), None),
+            Err(e) if e.is_connect() => (HttpErrorKind::Connect, e.to_string(), None),
+            Err(e) => (HttpErrorKind::Other, e.to_string(), None),
+        };
+
+        let retryable = match kind {
+            HttpErrorKind::Status(status) => status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error(),
+            HttpErrorKind::Connect | HttpErrorKind::Timeout => true,
+            HttpErrorKind::Cancelled | HttpErrorKind::Other => false,
+        };
+
+        let max_backoff = Duration::from_millis(settings.max_backoff_ms);
+        // Waiting less than the server asked for would just be rejected again.
+        let wait_too_long = retry_after.is_some_and(|delay| delay > max_backoff);
+
+        if !retryable || last_attempt || wait_too_long {
+            let mut detail = detail;
+            if let (true, Some(delay)) = (wait_too_long, retry_after) {
+                detail.push_str(&format!(" (server asked to retry after {}s)", delay.as_secs()));
+            }
+            return Err(HttpError { context: context.to_string(), kind, detail, retried });
+        }
+
+        retried.push(describe(kind));
+        if interrupt::sleep(retry_after.unwrap_or_else(|| backoff(settings, attempt))).is_err() {
+            return Err(cancelled(retried));
+        }
+    }
+
+    unreachable!("the last attempt always returns")
+}
+
+fn describe(kind: HttpErrorKind) -> String {
+    match kind {
+        HttpErrorKind::Status(status) => status.to_string(),
+        HttpErrorKind::Timeout => "timeout".to_string(),
+        HttpErrorKind::Connect => "connection failed".to_string(),
+        HttpErrorKind::Cancelled => "cancelled".to_string(),
+        HttpErrorKind::Other => "request failed".to_string(),
+    }
+}
+
+/// Reads `Retry-After` given in seconds. The HTTP-date form is rare from APIs
+/// and falls back to the regular backoff.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+    value.trim().parse::<u64>().ok().map(Duration::from_secs)
+}
+
+/// "Full jitter" backoff: a random delay up to the exponential ceiling, so
+/// parallel requests don't retry in lockstep.
+fn backoff(settings: &HttpSettings, attempt: u32) -> Duration {
+    let ceiling = settings.initial_backoff_ms
+        .saturating_mul(1u64 << attempt.min(20))
+        .min(settings.max_backoff_ms);
+    // RandomState is randomly seeded per instance, which is all the randomness jitter needs.
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_millis(random % (ceiling + 1))
+}
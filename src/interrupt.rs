@@ -0,0 +1,132 @@
+use std::fmt;
+use std::io::{BufRead, BufReader, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::http::{HttpError, HttpErrorKind};
+
+// Ctrl-C handling. While a request is in flight the first Ctrl-C only asks it
+// to stop, so the stream can be torn down cleanly; any other time (or on a
+// second press) the process exits as usual.
+
+static IN_FLIGHT: AtomicBool = AtomicBool::new(false);
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+pub fn install_handler() -> Result<(), ctrlc::Error> {
+    ctrlc::set_handler(|| {
+        if IN_FLIGHT.load(Ordering::SeqCst) && !CANCELLED.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        std::process::exit(130);
+    })
+}
+
+/// Marks a request as in flight until dropped.
+pub struct RequestGuard(());
+
+impl RequestGuard {
+    pub fn new() -> Self {
+        CANCELLED.store(false, Ordering::SeqCst);
+        IN_FLIGHT.store(true, Ordering::SeqCst);
+        RequestGuard(())
+    }
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        IN_FLIGHT.store(false, Ordering::SeqCst);
+    }
+}
+
+pub fn cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+/// Whether an error is the user's Ctrl-C, given while the answer streamed in
+/// or while the request was still being sent.
+pub fn is_cancellation(error: &(dyn std::error::Error + 'static)) -> bool {
+    error.is::<Cancelled>() || error.downcast_ref::<HttpError>().is_some_and(|e| e.kind == HttpErrorKind::Cancelled)
+}
+
+/// How often a stalled read, a blocked request or a backoff checks for Ctrl-C.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The lines of a response body, read on a separate thread so that Ctrl-C is
+/// noticed while the server sends nothing, not only when the next line comes.
+/// After Ctrl-C it yields `Cancelled` and stops; the reading thread is left to
+/// finish on its own when the connection closes or times out.
+pub fn lines(body: impl Read + Send + 'static) -> impl Iterator<Item = Result<String, Box<dyn std::error::Error>>> {
+    let (sender, receiver) = mpsc::sync_channel(64);
+    thread::spawn(move || {
+        for line in BufReader::new(body).lines() {
+            let failed = line.is_err();
+            if sender.send(line).is_err() || failed {
+                return;
+            }
+        }
+    });
+
+    let mut done = false;
+    std::iter::from_fn(move || {
+        while !done {
+            if cancelled() {
+                done = true;
+                return Some(Err(Box::new(Cancelled) as Box<dyn std::error::Error>));
+            }
+            match receiver.recv_timeout(POLL_INTERVAL) {
+                Ok(line) => return Some(line.map_err(Into::into)),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => done = true,
+            }
+        }
+        None
+    })
+}
+
+/// Runs `work` on a separate thread, so that Ctrl-C is noticed while it
+/// blocks, e.g. sending a request to a server that doesn't answer. After
+/// Ctrl-C the thread is left to finish on its own.
+pub fn run<T: Send + 'static>(work: impl FnOnce() -> T + Send + 'static) -> Result<T, Cancelled> {
+    if cancelled() {
+        return Err(Cancelled);
+    }
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || sender.send(work()));
+
+    loop {
+        match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok(result) => return Ok(result),
+            Err(RecvTimeoutError::Timeout) if cancelled() => return Err(Cancelled),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => panic!("worker thread panicked"),
+        }
+    }
+}
+
+/// Sleeps for `duration`, or until Ctrl-C.
+pub fn sleep(duration: Duration) -> Result<(), Cancelled> {
+    let deadline = Instant::now() + duration;
+    loop {
+        if cancelled() {
+            return Err(Cancelled);
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Ok(());
+        }
+        thread::sleep(left.min(POLL_INTERVAL));
+    }
+}
+
+/// Returned when the user aborts a request with Ctrl-C.
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "request cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
@@ -1,5 +1,22 @@
 mod shell;
+mod budget;
+mod cache;
+mod chat_client;
+mod consensus;
+mod escalation;
+mod fallback;
+mod http;
+mod interrupt;
 mod model;
+mod model_picker;
+mod provider;
+mod sampling;
+mod sanitize;
+mod sse;
+mod structured;
+mod usage;
+#[cfg(test)]
+mod test_server;
 mod openrouter_client; // NEW: Add the openrouter_client module
 
 use std::collections::HashMap;
@@ -11,20 +28,136 @@ use clap::{Command, Arg};
 use colored::*;
 use std::path::PathBuf;
 use shell::Shell;
-use crate::model::Model;
-use crate::openrouter_client::fetch_openrouter_models; // NEW: Import the fetch function
+use crate::budget::BudgetSettings;
+use crate::cache::{get_cache_path, load_cache, save_cache, CacheEntry};
+use crate::escalation::EscalationSettings;
+use crate::http::HttpSettings;
+use crate::interrupt::RequestGuard;
+use crate::model::{Model, Suggestion};
+use crate::provider::ollama::OllamaSettings;
+use crate::sampling::SamplingSettings;
+use crate::sanitize::UnusableOutput;
+use crate::openrouter_client::{cached_openrouter_models, fetch_openrouter_models, ProviderPreferences}; // NEW: Import the fetch function
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 struct Config {
     model: Model,
-    max_tokens: i32
+    /// Models tried in order when `model` is unreachable, rejects our
+    /// credentials or returns nothing.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    fallback_models: Vec<Model>,
+    /// Stronger model to retry on when the answer is empty, unusable or unsure.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    escalation: Option<EscalationSettings>,
+    max_tokens: i32,
+    #[serde(default = "default_stream")]
+    stream: bool,
+    #[serde(default)]
+    structured: bool,
+    #[serde(default = "default_candidates")]
+    candidates: u32,
+    #[serde(default)]
+    consensus: bool,
+    /// Print token usage and cost after each request.
+    #[serde(default)]
+    verbose: bool,
+    /// Models asked in parallel in consensus mode.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    consensus_models: Vec<Model>,
+    #[serde(default)]
+    http: HttpSettings,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    budget: Option<BudgetSettings>,
+    /// `keep_alive` and model options for Ollama requests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ollama: Option<OllamaSettings>,
+    /// Sampling parameters for every model.
+    #[serde(default, skip_serializing_if = "SamplingSettings::is_empty")]
+    sampling: SamplingSettings,
+    /// Sampling parameters for particular models, keyed like `ollama:qwen2.5-coder`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    model_sampling: HashMap<String, SamplingSettings>,
+    /// Sampling parameters given on the command line, for this run only.
+    #[serde(skip)]
+    cli_sampling: SamplingSettings,
 }
 
+impl Config {
+    /// Sampling parameters for a model: the command line over its entry in
+    /// `model_sampling` over `sampling`.
+    fn sampling_for(&self, model: &Model) -> SamplingSettings {
+        let mut sampling = self.sampling.clone();
+        if let Some(per_model) = self.model_sampling.get(&model.to_string()) {
+            sampling = sampling.overridden_by(per_model);
+        }
+        sampling.overridden_by(&self.cli_sampling)
+    }
+
+    /// The primary model followed by its fallbacks.
+    fn model_chain(&self) -> Vec<Model> {
+        std::iter::once(self.model.clone()).chain(self.fallback_models.iter().cloned()).collect()
+    }
+
+    /// The models a request may go to: the consensus models, or the primary
+    /// model, its fallbacks and the escalation model.
+    fn models_in_use(&self) -> Vec<Model> {
+        if self.consensus {
+            return self.consensus_models.clone();
+        }
+        let mut models = self.model_chain();
+        models.extend(self.escalation.as_ref().map(|escalation| escalation.model.clone()));
+        models
+    }
+
+    /// This configuration with every paid model removed and the budget's
+    /// Ollama fallback added, or `None` if no model would be left.
+    fn free_models_only(&self) -> Option<Config> {
+        let budget_fallback = self.budget.as_ref()
+            .and_then(|budget| budget.fallback_ollama_model.clone())
+            .map(Model::Ollama);
+        self.restricted_to(|model| !model.is_paid(), budget_fallback)
+    }
+
+    /// This configuration with only the models `keep` accepts in the chain,
+    /// escalation and consensus sets, and `last_resort` appended to the
+    /// chain, or `None` if the chain would be empty.
+    fn restricted_to(&self, keep: impl Fn(&Model) -> bool, last_resort: Option<Model>) -> Option<Config> {
+        let mut chain: Vec<Model> = self.model_chain().into_iter()
+            .filter(|model| keep(model))
+            .chain(last_resort)
+            .collect();
+        if chain.is_empty() {
+            return None;
+        }
+
+        let mut config = self.clone();
+        config.model = chain.remove(0);
+        config.fallback_models = chain;
+        config.escalation = config.escalation.filter(|escalation| keep(&escalation.model));
+        config.consensus_models.retain(|model| keep(model));
+        if config.consensus_models.len() < 2 {
+            config.consensus = false;
+        }
+        Some(config)
+    }
+}
+
+fn default_stream() -> bool {
+    true
+}
+
+fn default_candidates() -> u32 {
+    1
+}
+
+/// Flags that change the model or its sampling for a single run.
+const ONE_OFF_OVERRIDES: &[&str] = &["model", "provider", "max-tokens", "temperature", "top-p", "seed", "stop", "frequency-penalty", "presence-penalty"];
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let matches = Command::new("llm-term")
         .version("1.0")
         .author("dh1101")
-        .about("Generate terminal commands using OpenAI, OpenRouter, or local Ollama models")
+        .about("Generate terminal commands using OpenAI, Azure OpenAI, Anthropic, Gemini, OpenRouter, or local Ollama models")
         .arg(Arg::new("prompt")
             .help("The prompt describing the desired command")
             .required(false)
@@ -40,30 +173,217 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("Disable cache and always query the LLM")
                 .action(clap::ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("structured")
+                .long("structured")
+                .help("Ask the model for an explanation and risk assessment alongside the command")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("candidates")
+                .long("candidates")
+                .value_name("N")
+                .help("Generate N alternative commands and pick one from a list")
+                .value_parser(clap::value_parser!(u32).range(1..=10)),
+        )
+        .arg(
+            Arg::new("consensus")
+                .long("consensus")
+                .help("Ask the consensus models in parallel and compare their commands")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .help("Print token usage and cost for each request")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("usage")
+                .long("usage")
+                .help("Show token usage and cost per day and per model, from the usage ledger")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no-stream")
+                .long("no-stream")
+                .help("Wait for the whole command instead of streaming it as it is generated")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("model")
+                .long("model")
+                .value_name("MODEL")
+                .help("Model for this run, as provider:name (e.g. ollama:qwen2.5-coder) or a name for the configured provider; config.json is left as it is"),
+        )
+        .arg(
+            Arg::new("provider")
+                .long("provider")
+                .value_name("PROVIDER")
+                .help("Provider of the --model name")
+                .value_parser(clap::builder::PossibleValuesParser::new(model::PROVIDER_NAMES))
+                .requires("model"),
+        )
+        .arg(
+            Arg::new("max-tokens")
+                .long("max-tokens")
+                .value_name("N")
+                .help("Maximum tokens in the answer for this run")
+                .value_parser(clap::value_parser!(i32).range(1..)),
+        )
+        .arg(
+            Arg::new("temperature")
+                .long("temperature")
+                .value_name("T")
+                .help("Sampling temperature for this run (ignored by reasoning models)")
+                .value_parser(clap::value_parser!(f32)),
+        )
+        .arg(
+            Arg::new("top-p")
+                .long("top-p")
+                .value_name("P")
+                .help("Nucleus sampling probability mass for this run")
+                .value_parser(clap::value_parser!(f32)),
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .value_name("N")
+                .help("Seed for reproducible answers, where the backend supports one")
+                .value_parser(clap::value_parser!(i64)),
+        )
+        .arg(
+            Arg::new("stop")
+                .long("stop")
+                .value_name("TEXT")
+                .help("Stop generating at TEXT; may be given several times")
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("frequency-penalty")
+                .long("frequency-penalty")
+                .value_name("X")
+                .help("Frequency penalty for this run")
+                .value_parser(clap::value_parser!(f32)),
+        )
+        .arg(
+            Arg::new("presence-penalty")
+                .long("presence-penalty")
+                .value_name("X")
+                .help("Presence penalty for this run")
+                .value_parser(clap::value_parser!(f32)),
+        )
+        .subcommand(
+            Command::new("openrouter")
+                .about("Inspect the OpenRouter account behind OPENROUTER_API_KEY")
+                .subcommand_required(true)
+                .subcommand(Command::new("credits").about("Show remaining credits, the key's limit and its rate limit"))
+                .subcommand(
+                    Command::new("generation")
+                        .about("Show token counts, provider, latency and cost of a generation")
+                        .arg(Arg::new("id")
+                            .help("Generation id; defaults to the latest OpenRouter request in the usage ledger")
+                            .required(false)
+                            .index(1)),
+                ),
+        )
         .get_matches();
 
+    interrupt::install_handler()?;
+
     let config_path = get_default_config_path().expect("Failed to get default config path");
+    // Timeouts and retries apply to the subcommands and to the model lists
+    // fetched while configuring, too.
+    http::configure(load_http_settings(&config_path));
+
+    if let Some(("openrouter", openrouter_matches)) = matches.subcommand() {
+        return match openrouter_matches.subcommand() {
+            Some(("credits", _)) => show_openrouter_credits(),
+            Some(("generation", generation_matches)) => show_openrouter_generation(generation_matches.get_one::<String>("id")),
+            _ => unreachable!("a subcommand is required"),
+        };
+    }
 
     if matches.get_flag("config") {
-        let config = create_config()?;
+        let existing = match fs::read_to_string(&config_path) {
+            Ok(content) => match serde_json::from_str(&content) {
+                Ok(existing) => Some(existing),
+                Err(e) => {
+                    println!("{}", format!("Couldn't read the existing configuration ({}); starting a new one.", e).yellow());
+                    None
+                }
+            },
+            Err(_) => None,
+        };
+        let config = create_config(existing)?;
         let content = serde_json::to_string_pretty(&config)?;
         fs::write(&config_path, content)?;
         println!("{}", "Configuration saved successfully.".green());
         return Ok(());
     }
 
-    let config = load_or_create_config(&config_path)?;
+    let mut config = load_or_create_config(&config_path)?;
+    if matches.get_flag("no-stream") {
+        config.stream = false;
+    }
+    if matches.get_flag("structured") {
+        config.structured = true;
+    }
+    if let Some(candidates) = matches.get_one::<u32>("candidates") {
+        config.candidates = *candidates;
+    }
+    if matches.get_flag("consensus") {
+        config.consensus = true;
+    }
+    if matches.get_flag("verbose") {
+        config.verbose = true;
+    }
+    if let Some(spec) = matches.get_one::<String>("model") {
+        config.model = config.model.overridden_by(spec, matches.get_one::<String>("provider").map(String::as_str))?;
+    }
+    if let Some(max_tokens) = matches.get_one::<i32>("max-tokens") {
+        config.max_tokens = *max_tokens;
+        // An explicit limit also beats `num_predict` in the Ollama options.
+        if let Some(ollama) = &mut config.ollama {
+            ollama.options.remove("num_predict");
+        }
+    }
+    provider::ollama::configure(config.ollama.clone().unwrap_or_default());
+    config.cli_sampling = SamplingSettings {
+        temperature: matches.get_one::<f32>("temperature").copied(),
+        top_p: matches.get_one::<f32>("top-p").copied(),
+        stop: matches.get_many::<String>("stop").map(|values| values.cloned().collect()).unwrap_or_default(),
+        frequency_penalty: matches.get_one::<f32>("frequency-penalty").copied(),
+        presence_penalty: matches.get_one::<f32>("presence-penalty").copied(),
+        seed: matches.get_one::<i64>("seed").copied(),
+    };
+
+    if matches.get_flag("usage") {
+        return usage::print_report();
+    }
 
     let cache_path = get_cache_path()?;
     let mut cache = load_cache(&cache_path)?;
 
     if let Some(prompt) = matches.get_one::<String>("prompt") {
-        let disable_cache = matches.get_flag("disable-cache");
+        // A one-off model or sampling override asks a different question than
+        // the cached answer was given for, and its answer isn't the usual one.
+        // Consensus mode and several candidates are asked for to compare fresh
+        // answers, so they don't take the cached one either, though the chosen
+        // command is saved.
+        let one_off = ONE_OFF_OVERRIDES.iter().any(|id| matches.contains_id(id));
+        let disable_cache = matches.get_flag("disable-cache") || one_off || config.consensus || config.candidates > 1;
+        let save_to = (!one_off).then_some(&cache_path);
 
         if !disable_cache {
-            if let Some(cached_command) = cache.get(prompt) {
+            if let Some(cached) = cache.get(prompt).filter(|cached| cached.is_from_any(&config.model_chain())) {
+                let cached_command = cached.command();
                 println!("{}", "This command exists in cache".yellow());
                 println!("{}", cached_command.cyan().bold());
+                if let CacheEntry::Generated { escalation: Some(escalation), .. } = cached {
+                    println!("{}", format!("Escalated from {} ({})", escalation.from, escalation.reason).dimmed());
+                }
                 println!("{}", "Do you want to execute this command? (y/n)".yellow());
 
                 let mut user_input = String::new();
@@ -79,17 +399,17 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     if user_input.trim().to_lowercase() == "y" {
                         cache.remove(prompt);
                         save_cache(&cache_path, &cache)?;
-                        get_command_from_llm(&config, &mut cache, &cache_path, prompt)?;
+                        get_command_from_llm(&config, &mut cache, save_to, prompt)?;
                     } else {
                         println!("{}", "Command execution cancelled.".yellow());
                     }
                 }
                 return Ok(());
             } else {
-                get_command_from_llm(&config, &mut cache, &cache_path, prompt)?;
+                get_command_from_llm(&config, &mut cache, save_to, prompt)?;
             }
         } else {
-            get_command_from_llm(&config, &mut cache, &cache_path, prompt)?;
+            get_command_from_llm(&config, &mut cache, save_to, prompt)?;
         }
     } else {
         println!("{}", "Please provide a prompt or use --config to set up the configuration.".yellow());
@@ -104,26 +424,38 @@ fn get_default_config_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
     Ok(exe_dir.join("config.json"))
 }
 
+/// The `http` section of the config file, or the defaults if there is no
+/// readable one.
+fn load_http_settings(path: &PathBuf) -> HttpSettings {
+    fs::read_to_string(path).ok()
+        .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok())
+        .and_then(|mut config| config.get_mut("http").map(serde_json::Value::take))
+        .and_then(|http| serde_json::from_value(http).ok())
+        .unwrap_or_default()
+}
+
 fn load_or_create_config(path: &PathBuf) -> Result<Config, Box<dyn std::error::Error>> {
     if let Ok(content) = fs::read_to_string(path) {
         Ok(serde_json::from_str(&content)?)
     } else {
-        let config = create_config()?;
+        let config = create_config(None)?;
         let content = serde_json::to_string_pretty(&config)?;
         fs::write(path, content)?;
         Ok(config)
     }
 }
 
-fn create_config() -> Result<Config, io::Error> {
+/// Asks for the model and its token limit. Everything else is kept from
+/// `existing`, so that re-running `--config` only switches the model.
+fn create_config(existing: Option<Config>) -> Result<Config, io::Error> {
     // This outer loop is for selecting the model provider
-    let mut selected_model_enum;
-    let mut model_context_length_opt;
+    let selected_model_enum;
+    let model_context_length_opt;
     
     loop {
         println!(
             "{}",
-            "Select model provider:\n 1 for OpenAI (gpt-4o-mini)\n 2 for OpenAI (gpt-4o)\n 3 for Ollama\n 4 for OpenRouter".cyan()
+            "Select model provider:\n 1 for OpenAI\n 2 for Ollama\n 3 for OpenRouter\n 4 for Anthropic\n 5 for Google Gemini\n 6 for Azure OpenAI\n 7 for another OpenAI-compatible server (vLLM, LM Studio, llama.cpp, ...)\n 8 for a local GGUF model file (offline, CPU)".cyan()
         );
         io::stdout().flush()?;
         let mut choice = String::new();
@@ -131,27 +463,22 @@ fn create_config() -> Result<Config, io::Error> {
 
         match choice.trim() {
             "1" => {
-                selected_model_enum = Model::OpenAiGpt4oMini;
-                model_context_length_opt = Some(128_000);
+                let Some(model_id) = model_picker::pick_openai_model()? else {
+                    continue;
+                };
+                model_context_length_opt = provider::openai::max_output_tokens(&model_id);
+                selected_model_enum = Model::OpenAi { model_name: model_id };
                 break;
             }
             "2" => {
-                selected_model_enum = Model::OpenAiGpt4o;
-                model_context_length_opt = Some(128_000);
-                break;
-            }
-            "3" => {
-                println!("{}", "Enter Ollama model name (default: llama3.1):".cyan());
-                io::stdout().flush()?;
-                let mut ollama_model_name = String::new();
-                io::stdin().read_line(&mut ollama_model_name)?;
-                let trimmed_name = ollama_model_name.trim();
-                let model_id = if trimmed_name.is_empty() { "llama3.1".to_string() } else { trimmed_name.to_string() };
+                let Some(model_id) = model_picker::pick_ollama_model()? else {
+                    continue;
+                };
                 selected_model_enum = Model::Ollama(model_id);
                 model_context_length_opt = None;
                 break;
             }
-            "4" => { // OpenRouter selection
+            "3" => { // OpenRouter selection
                 let openrouter_api_key = match std::env::var("OPENROUTER_API_KEY") {
                     Ok(key) => key,
                     Err(_) => {
@@ -163,53 +490,209 @@ fn create_config() -> Result<Config, io::Error> {
 
                 println!("{}", "Fetching models from OpenRouter...".yellow());
                 match fetch_openrouter_models(&openrouter_api_key) {
-                    Ok(mut available_models) => {
-                        if available_models.is_empty() {
-                            println!("{}", "No suitable models found on OpenRouter (they might be missing context length info). Try manual entry or another provider.".yellow());
-                            continue; // Restart provider selection
-                        }
-                        // Filter for models with known context length for simplicity
-                        available_models.retain(|m| m.context_length.is_some());
-
-                        println!("{}", "Select an OpenRouter model:".cyan());
-                        for (idx, model_info) in available_models.iter().enumerate() {
-                            println!(" {}. {} (Context: {} tokens)", idx + 1, model_info.id, model_info.context_length.unwrap_or(0));
-                        }
-
-                        // Inner loop for selecting a specific OpenRouter model from the list
-                        loop {
-                            print!("{}", "Enter model number: ".cyan());
-                            io::stdout().flush()?;
-                            let mut model_choice_str = String::new();
-                            io::stdin().read_line(&mut model_choice_str)?;
-                            match model_choice_str.trim().parse::<usize>() {
-                                Ok(num) if num > 0 && num <= available_models.len() => {
-                                    let chosen_or_model = available_models[num - 1].clone();
-                                    selected_model_enum = Model::OpenRouter { model_name: chosen_or_model.id };
-                                    model_context_length_opt = chosen_or_model.context_length;
-                                    break;
-                                }
-                                _ => println!("{}", "Invalid selection. Please enter a valid number from the list.".red()),
-                            }
+                    Ok(available_models) if !available_models.is_empty() => {
+                        if let Some(chosen_or_model) = model_picker::pick_openrouter_model(&available_models)? {
+                            selected_model_enum = openrouter_model(chosen_or_model.id);
+                            model_context_length_opt = chosen_or_model.context_length;
+                            break;
                         }
                     }
+                    Ok(_) => {
+                        println!("{}", "No suitable models found on OpenRouter (they might be missing context length info).".yellow());
+                    }
                     Err(e) => {
                         println!("{}", format!("Error fetching OpenRouter models: {}", e).red());
                         println!("{}", "Falling back to manual OpenRouter model entry.".yellow());
-                        println!("{}", "Enter OpenRouter model identifier manually (e.g., mistralai/mistral-7b-instruct):".cyan());
-                        io::stdout().flush()?;
-                        let mut or_model_name_manual = String::new();
-                        io::stdin().read_line(&mut or_model_name_manual)?;
-                        let trimmed_name_manual = or_model_name_manual.trim();
-                        if trimmed_name_manual.is_empty() {
-                            println!("{}", "OpenRouter model name cannot be empty if entered manually. Retrying provider selection.".red());
-                            continue;
-                        }
-                        selected_model_enum = Model::OpenRouter { model_name: trimmed_name_manual.to_string() };
-                        model_context_length_opt = None;
+                    }
+                }
+
+                println!("{}", "Enter OpenRouter model identifier manually (e.g., mistralai/mistral-7b-instruct):".cyan());
+                io::stdout().flush()?;
+                let mut or_model_name_manual = String::new();
+                io::stdin().read_line(&mut or_model_name_manual)?;
+                let trimmed_name_manual = or_model_name_manual.trim();
+                if trimmed_name_manual.is_empty() {
+                    println!("{}", "OpenRouter model name cannot be empty if entered manually. Retrying provider selection.".red());
+                    continue;
+                }
+                // Validate against the cached catalogue, if there is one, so max tokens can be checked offline too.
+                let cached_model = cached_openrouter_models()
+                    .and_then(|models| models.into_iter().find(|m| m.id == trimmed_name_manual));
+                if cached_model.is_none() {
+                    println!("{}", format!("'{}' is not in the cached OpenRouter catalogue; its context length can't be checked.", trimmed_name_manual).yellow());
+                }
+                selected_model_enum = openrouter_model(trimmed_name_manual.to_string());
+                model_context_length_opt = cached_model.and_then(|m| m.context_length);
+                break;
+            }
+            "4" => { // Anthropic selection
+                if std::env::var("ANTHROPIC_API_KEY").is_err() {
+                    println!("{}", "ANTHROPIC_API_KEY environment variable not set.".red());
+                    println!("{}", "Please set it and try again, or choose another provider.".yellow());
+                    continue;
+                }
+
+                let probe = Model::Anthropic { model_name: String::new() };
+                if let Ok(available) = probe.provider().list_models() {
+                    if !available.is_empty() {
+                        println!("{}", format!("Available Anthropic models: {}", available.join(", ")).cyan());
+                    }
+                }
+                println!("{}", "Enter Anthropic model name (default: claude-3-5-haiku-latest):".cyan());
+                io::stdout().flush()?;
+                let mut anthropic_model_name = String::new();
+                io::stdin().read_line(&mut anthropic_model_name)?;
+                let trimmed_name = anthropic_model_name.trim();
+                let model_id = if trimmed_name.is_empty() { "claude-3-5-haiku-latest".to_string() } else { trimmed_name.to_string() };
+                model_context_length_opt = provider::anthropic::max_output_tokens(&model_id);
+                selected_model_enum = Model::Anthropic { model_name: model_id };
+                break;
+            }
+            "5" => { // Gemini selection
+                if std::env::var("GEMINI_API_KEY").is_err() {
+                    println!("{}", "GEMINI_API_KEY environment variable not set.".red());
+                    println!("{}", "Please set it and try again, or choose another provider.".yellow());
+                    continue;
+                }
+
+                let probe = provider::GeminiProvider { model_name: String::new() };
+                let available = probe.list_generation_models().unwrap_or_else(|e| {
+                    println!("{}", format!("Couldn't list Gemini models: {}", e).yellow());
+                    Vec::new()
+                });
+                if !available.is_empty() {
+                    let names: Vec<&str> = available.iter().map(|model| model.name.as_str()).collect();
+                    println!("{}", format!("Available Gemini models: {}", names.join(", ")).cyan());
+                }
+                let default_name = available.first().map(|model| model.name.clone()).unwrap_or_default();
+                let entered = if default_name.is_empty() {
+                    prompt_line("Enter Gemini model name:")?
+                } else {
+                    prompt_line(&format!("Enter Gemini model name (default: {}):", default_name))?
+                };
+                let model_id = if entered.is_empty() { default_name } else { entered };
+                if model_id.is_empty() {
+                    println!("{}", "Model name cannot be empty. Retrying provider selection.".red());
+                    continue;
+                }
+                let listed = available.iter().find(|model| model.name == model_id);
+                if let Some(input_token_limit) = listed.and_then(|model| model.input_token_limit) {
+                    println!("{}", format!("{} accepts up to {} input tokens.", model_id, input_token_limit).cyan());
+                }
+                // The output limit, not the context, is what bounds max tokens.
+                model_context_length_opt = listed.and_then(|model| model.output_token_limit);
+                selected_model_enum = Model::Gemini { model_name: model_id };
+                break;
+            }
+            "6" => { // Azure OpenAI selection
+                if std::env::var("AZURE_OPENAI_API_KEY").is_err() {
+                    println!("{}", "AZURE_OPENAI_API_KEY environment variable not set.".red());
+                    println!("{}", "Please set it and try again, or choose another provider.".yellow());
+                    continue;
+                }
+
+                let default_endpoint = std::env::var("AZURE_OPENAI_ENDPOINT").unwrap_or_default();
+                let endpoint = if default_endpoint.is_empty() {
+                    prompt_line("Enter Azure OpenAI resource endpoint (e.g., https://my-resource.openai.azure.com):")?
+                } else {
+                    let entered = prompt_line(&format!("Enter Azure OpenAI resource endpoint (default: {}):", default_endpoint))?;
+                    if entered.is_empty() { default_endpoint } else { entered }
+                };
+                if endpoint.is_empty() {
+                    println!("{}", "Azure endpoint cannot be empty. Retrying provider selection.".red());
+                    continue;
+                }
+
+                let deployment = prompt_line("Enter Azure OpenAI deployment name:")?;
+                if deployment.is_empty() {
+                    println!("{}", "Azure deployment name cannot be empty. Retrying provider selection.".red());
+                    continue;
+                }
+
+                let api_version = prompt_line(&format!("Enter Azure OpenAI api-version (default: {}):", model::AZURE_DEFAULT_API_VERSION))?;
+                let api_version = if api_version.is_empty() { model::AZURE_DEFAULT_API_VERSION.to_string() } else { api_version };
+
+                let underlying = prompt_line("Enter the OpenAI model this deployment runs, e.g. gpt-4o or o3-mini (optional):")?;
+                let model = (!underlying.is_empty()).then_some(underlying);
+
+                selected_model_enum = Model::AzureOpenAi { endpoint, deployment, api_version, model };
+                model_context_length_opt = None;
+                break;
+            }
+            "7" => { // Generic OpenAI-compatible server
+                let base_url = prompt_line("Enter the server's OpenAI-compatible base URL (e.g., http://gpu-box:8000/v1):")?;
+                if base_url.is_empty() {
+                    println!("{}", "Base URL cannot be empty. Retrying provider selection.".red());
+                    continue;
+                }
+
+                let api_key_env = prompt_line("Enter the environment variable holding the API key (leave empty if none):")?;
+                let api_key_env = if api_key_env.is_empty() { None } else { Some(api_key_env) };
+
+                let mut extra_headers = HashMap::new();
+                println!("{}", "Enter extra request headers as 'Name: value', one per line (empty line to finish):".cyan());
+                loop {
+                    let header_line = prompt_line(">")?;
+                    if header_line.is_empty() {
                         break;
                     }
+                    match header_line.split_once(':') {
+                        Some((name, value)) if !name.trim().is_empty() => {
+                            extra_headers.insert(name.trim().to_string(), value.trim().to_string());
+                        }
+                        _ => println!("{}", "Invalid header. Use the form 'Name: value'.".red()),
+                    }
+                }
+
+                let mut probe = Model::OpenAiCompatible {
+                    base_url,
+                    model_name: String::new(),
+                    api_key_env,
+                    extra_headers,
+                };
+                let served_models = probe.provider().list_models().unwrap_or_default();
+                if !served_models.is_empty() {
+                    println!("{}", format!("Models served: {}", served_models.join(", ")).cyan());
+                }
+
+                let default_name = served_models.first().cloned().unwrap_or_default();
+                let entered = if default_name.is_empty() {
+                    prompt_line("Enter model name:")?
+                } else {
+                    prompt_line(&format!("Enter model name (default: {}):", default_name))?
+                };
+                let chosen_name = if entered.is_empty() { default_name } else { entered };
+                if chosen_name.is_empty() {
+                    println!("{}", "Model name cannot be empty. Retrying provider selection.".red());
+                    continue;
+                }
+
+                if let Model::OpenAiCompatible { model_name, .. } = &mut probe {
+                    *model_name = chosen_name;
+                }
+                selected_model_enum = probe;
+                model_context_length_opt = None;
+                break;
+            }
+            "8" => { // GGUF model run in-process
+                if !provider::local::AVAILABLE {
+                    println!("{}", "This build can't run local models. Rebuild llm-term with `cargo build --release --features local`.".yellow());
+                }
+                println!("{}", format!("Supported architectures: {}.", provider::local::SUPPORTED_ARCHITECTURES.join(", ")).cyan());
+                let gguf_path = PathBuf::from(prompt_line("Enter the path to the GGUF model file:")?);
+                let tokenizer_path = prompt_line("Enter the path to its tokenizer.json (default: next to the model file):")?;
+                let tokenizer_path = if tokenizer_path.is_empty() { None } else { Some(PathBuf::from(tokenizer_path)) };
+                if let Err(e) = provider::local::check_files(&gguf_path, tokenizer_path.as_deref()) {
+                    println!("{}", format!("{}. Retrying provider selection.", e).red());
+                    continue;
                 }
+
+                // config.json lives next to the binary, so relative paths would depend on where llm-term is run from.
+                let gguf_path = gguf_path.canonicalize().unwrap_or(gguf_path);
+                let tokenizer_path = tokenizer_path.map(|path| path.canonicalize().unwrap_or(path));
+                selected_model_enum = Model::Local { gguf_path, tokenizer_path };
+                model_context_length_opt = None;
+                break;
             }
             _ => {
                 println!("{}", "Invalid choice. Please try again.".red());
@@ -219,14 +702,16 @@ fn create_config() -> Result<Config, io::Error> {
     };
 
     // Prompt for max_tokens, using the fetched context length if available
-    let default_max_tokens_value = 150;
+    // Reasoning models spend their hidden reasoning from the same budget, so
+    // 150 tokens often leaves nothing for the answer.
+    let default_max_tokens_value = if selected_model_enum.provider().is_reasoning_model() { 2048 } else { 150 };
     let max_tokens_upper_bound = model_context_length_opt.unwrap_or(4096);
 
     let max_tokens_prompt = format!(
         "Enter max tokens for completion (1-{}, default {}{}): ",
         max_tokens_upper_bound,
         default_max_tokens_value,
-        model_context_length_opt.map_or("".to_string(), |cl| format!(", model context: {}", cl))
+        model_context_length_opt.map_or("".to_string(), |cl| format!(", model limit: {}", cl))
     );
 
     let final_max_tokens = loop {
@@ -244,7 +729,7 @@ fn create_config() -> Result<Config, io::Error> {
                 if tokens > 0 && tokens <= max_tokens_upper_bound {
                     break tokens;
                 } else if tokens > max_tokens_upper_bound {
-                    println!("{}", format!("Max tokens for completion cannot exceed model context limit of {} (or chosen upper bound).", max_tokens_upper_bound).red());
+                    println!("{}", format!("Max tokens for completion cannot exceed the model's limit of {} (or chosen upper bound).", max_tokens_upper_bound).red());
                 } else {
                     println!("{}", "Max tokens must be a positive number.".red());
                 }
@@ -255,41 +740,221 @@ fn create_config() -> Result<Config, io::Error> {
         }
     };
 
+    if let Some(existing) = existing {
+        return Ok(Config { model: selected_model_enum, max_tokens: final_max_tokens, ..existing });
+    }
+
     Ok(Config {
         model: selected_model_enum,
+        fallback_models: Vec::new(),
+        escalation: None,
         max_tokens: final_max_tokens,
+        stream: default_stream(),
+        structured: false,
+        candidates: default_candidates(),
+        consensus: false,
+        verbose: false,
+        consensus_models: Vec::new(),
+        http: HttpSettings::default(),
+        budget: None,
+        ollama: None,
+        sampling: SamplingSettings::default(),
+        model_sampling: HashMap::new(),
+        cli_sampling: SamplingSettings::default(),
     })
 }
 
-fn get_cache_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
-    let exe_path = std::env::current_exe()?;
-    let exe_dir = exe_path.parent().ok_or("Failed to get executable directory")?;
-    Ok(exe_dir.join("cache.json"))
-}
+fn show_openrouter_credits() -> Result<(), Box<dyn std::error::Error>> {
+    let api_key = std::env::var("OPENROUTER_API_KEY").map_err(|_| "OPENROUTER_API_KEY environment variable not set")?;
+    let credits = openrouter_client::fetch_credits(&api_key)?;
+    let key = openrouter_client::fetch_key_info(&api_key)?;
 
-fn load_cache(path: &PathBuf) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
-    if let Ok(content) = fs::read_to_string(path) {
-        Ok(serde_json::from_str(&content)?)
-    } else {
-        Ok(HashMap::new())
+    println!("{}", "OpenRouter account".cyan().bold());
+    println!(
+        "Credits: ${:.4} remaining (${:.4} bought, ${:.4} used)",
+        credits.total_credits - credits.total_usage, credits.total_credits, credits.total_usage,
+    );
+    if let Some(label) = &key.label {
+        println!("Key: {}{}", label, if key.is_free_tier { " (free tier)" } else { "" });
     }
+    match (key.limit, key.limit_remaining) {
+        (Some(limit), Some(remaining)) => println!("Key limit: ${:.4} (${:.4} remaining)", limit, remaining),
+        (Some(limit), None) => println!("Key limit: ${:.4}", limit),
+        _ => println!("Key limit: none"),
+    }
+    if let Some(usage) = key.usage {
+        println!("Used by this key: ${:.4}", usage);
+    }
+    if let Some(rate_limit) = &key.rate_limit {
+        if let (Some(requests), Some(interval)) = (rate_limit.requests, &rate_limit.interval) {
+            println!("Rate limit: {} requests per {}", requests, interval);
+        }
+    }
+    Ok(())
 }
 
-fn save_cache(path: &PathBuf, cache: &HashMap<String, String>) -> Result<(), Box<dyn std::error::Error>> {
-    let content = serde_json::to_string_pretty(&cache)?;
-    fs::write(path, content)?;
+fn show_openrouter_generation(id: Option<&String>) -> Result<(), Box<dyn std::error::Error>> {
+    let api_key = std::env::var("OPENROUTER_API_KEY").map_err(|_| "OPENROUTER_API_KEY environment variable not set")?;
+    let id = match id {
+        Some(id) => id.clone(),
+        None => usage::load_ledger()?.into_iter().rev()
+            .filter(|entry| entry.model.starts_with("openrouter:"))
+            .find_map(|entry| entry.generation_id)
+            .ok_or("No OpenRouter generation in the usage ledger; pass a generation id")?,
+    };
+    let stats = openrouter_client::fetch_generation(&api_key, &id)?;
+
+    println!("{}", format!("Generation {}", stats.id).cyan().bold());
+    if let Some(model) = &stats.model {
+        println!("Model: {}", model);
+    }
+    if let Some(provider) = &stats.provider_name {
+        println!("Provider: {}", provider);
+    }
+    if let Some(created_at) = &stats.created_at {
+        println!("Created: {}", created_at);
+    }
+    let count = |tokens: Option<u64>| tokens.map(|tokens| tokens.to_string()).unwrap_or_else(|| "?".to_string());
+    println!("Tokens: {} prompt + {} completion", count(stats.tokens_prompt), count(stats.tokens_completion));
+    println!("Native tokens: {} prompt + {} completion", count(stats.native_tokens_prompt), count(stats.native_tokens_completion));
+    if let Some(reasoning) = stats.native_tokens_reasoning.filter(|tokens| *tokens > 0) {
+        println!("Native reasoning tokens: {}", reasoning);
+    }
+    if let Some(latency) = stats.latency {
+        let mut line = format!("Latency: {:.0} ms", latency);
+        if let Some(generation_time) = stats.generation_time {
+            line.push_str(&format!(", generation {:.0} ms", generation_time));
+        }
+        if stats.streamed {
+            line.push_str(" (streamed)");
+        }
+        println!("{}", line);
+    }
+    if let Some(cost) = stats.total_cost {
+        println!("Cost: ${:.6}", cost);
+    }
+    if let Some(finish_reason) = &stats.finish_reason {
+        println!("Finish reason: {}", finish_reason);
+    }
     Ok(())
 }
 
+/// A new OpenRouter model entry. Routing is restricted to providers that
+/// don't store or train on prompts; edit `provider` in config.json to change it.
+fn openrouter_model(model_name: String) -> Model {
+    Model::OpenRouter {
+        model_name,
+        provider: Some(ProviderPreferences::deny_data_collection()),
+        models: Vec::new(),
+    }
+}
+
+/// Prints a prompt and reads one trimmed line from stdin.
+fn prompt_line(prompt: &str) -> Result<String, io::Error> {
+    println!("{}", prompt.cyan());
+    io::stdout().flush()?;
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    Ok(input.trim().to_string())
+}
+
 fn get_command_from_llm(
     config: &Config,
-    cache: &mut HashMap<String, String>,
-    cache_path: &PathBuf,
-    prompt: &String,
+    cache: &mut HashMap<String, CacheEntry>,
+    cache_path: Option<&PathBuf>,
+    prompt: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    match config.model.llm_get_command(config, prompt.as_str()) {
-        Ok(Some(command)) => {
+    // Spending on models of unknown cost couldn't be counted against a hard
+    // dollar limit, so they are left out while one is set.
+    let known_cost;
+    let config = if config.budget.as_ref().is_some_and(|budget| budget.has_hard_usd_limit()) {
+        let unknown: Vec<String> = config.models_in_use().iter()
+            .filter(|model| !usage::cost_is_known(model))
+            .map(|model| model.to_string())
+            .collect();
+        if unknown.is_empty() {
+            config
+        } else {
+            let restricted = config.restricted_to(|model| !unknown.contains(&model.to_string()), None)
+                .or_else(|| config.free_models_only());
+            let Some(restricted) = restricted else {
+                eprintln!("{}", format!("Budget: the cost of requests to {} is unknown, so they can't be counted against the hard dollar limit. Refusing to call them; use hard_tokens to cap these providers.", unknown.join(", ")).red());
+                return Ok(());
+            };
+            eprintln!("{}", format!("Budget: the cost of requests to {} is unknown, so they can't be counted against the hard dollar limit. Skipping them; use hard_tokens to cap these providers.", unknown.join(", ")).yellow());
+            known_cost = restricted;
+            &known_cost
+        }
+    } else {
+        config
+    };
+
+    let restricted;
+    let config = match config.budget.as_ref().map(budget::check).transpose()? {
+        None | Some(budget::Status::WithinBudget) => config,
+        Some(budget::Status::OverSoftLimit(reason)) => {
+            eprintln!("{}", format!("Budget warning: {}.", reason).yellow());
+            config
+        }
+        Some(budget::Status::OverHardLimit(reason)) => match config.free_models_only() {
+            Some(free) => {
+                eprintln!("{}", format!("Budget: {}. Using {} instead of paid providers.", reason, free.model).yellow());
+                restricted = free;
+                &restricted
+            }
+            None => {
+                eprintln!("{}", format!("Budget: {}. Refusing to call paid providers.", reason).red());
+                return Ok(());
+            }
+        },
+    };
+
+    let (result, escalation, several_models) = if config.consensus {
+        let result = consensus::run(&config.consensus_models, config, prompt)
+            .map(|answer| answer.map(|(model, suggestion)| (model, vec![suggestion])));
+        (result, None, false)
+    } else {
+        let chain = config.model_chain();
+        let mut last_tried = None;
+        let mut result = fallback::first_success(&chain, |model| {
+            last_tried = Some(model.clone());
+            let suggestions = ask_model(model, config, prompt)?;
+            Ok(if suggestions.is_empty() { None } else { Some(suggestions) })
+        });
+
+        let escalation = match (&config.escalation, &last_tried) {
+            (Some(settings), Some(tried)) => escalation::escalate(settings, tried, &mut result, |model| ask_model(model, config, prompt)),
+            _ => None,
+        };
+        (result, escalation, chain.len() > 1)
+    };
+
+    if config.verbose {
+        usage::print_recorded();
+    }
+
+    match result {
+        Ok(Some((model, mut suggestions))) => {
+            if several_models || escalation.is_some() {
+                println!("{}", format!("Generated by {}", model).dimmed());
+            }
+            let chosen = if suggestions.len() == 1 {
+                0
+            } else {
+                match pick_suggestion(&suggestions)? {
+                    Some(index) => index,
+                    None => {
+                        println!("{}", "Command execution cancelled.".yellow());
+                        return Ok(());
+                    }
+                }
+            };
+            let Suggestion { command, details, .. } = suggestions.swap_remove(chosen);
+
             println!("{}", &command.cyan().bold());
+            if let Some(details) = &details {
+                details.print();
+            }
             println!("{}", "Do you want to execute this command? (y/n)".yellow());
 
             let mut user_input = String::new();
@@ -301,20 +966,85 @@ fn get_command_from_llm(
                 println!("{}", "Command execution cancelled.".yellow());
             }
 
-            cache.insert(prompt.clone(), command.clone());
-            save_cache(cache_path, cache)?;
+            cache.insert(prompt.to_string(), CacheEntry::Generated { command, model: model.to_string(), escalation });
+            if let Some(cache_path) = cache_path {
+                save_cache(cache_path, cache)?;
+            }
         },
         Ok(None) => println!("{}", "No command could be generated.".yellow()),
+        Err(e) if interrupt::is_cancellation(e.as_ref()) => println!("{}", "Request cancelled.".yellow()),
+        Err(e) if e.is::<UnusableOutput>() => {
+            let unusable = e.downcast_ref::<UnusableOutput>().expect("checked above");
+            println!("{}", unusable.raw.dimmed());
+            println!("{}", format!("Not offering to execute this: {}.", unusable.reason).red());
+        }
         Err(e) => eprintln!("{}", format!("Error getting command from LLM: {}", e).red()),
     }
 
     Ok(())
 }
 
+/// Gets the configured number of suggestions from one model, streaming the
+/// answer to the terminal when that is enabled.
+fn ask_model(model: &Model, config: &Config, prompt: &str) -> Result<Vec<Suggestion>, Box<dyn std::error::Error>> {
+    let _guard = RequestGuard::new();
+    if config.candidates > 1 {
+        model.llm_get_candidates(config, prompt, config.candidates)
+    } else if config.stream && !config.structured {
+        // Streaming a JSON object token by token isn't worth watching.
+        let mut streamed = false;
+        let result = model.llm_stream_command(config, prompt, &mut |token| {
+            streamed = true;
+            print!("{}", token.dimmed());
+            let _ = io::stdout().flush();
+        });
+        if streamed {
+            println!();
+        }
+        result.map(|suggestion| suggestion.into_iter().collect())
+    } else {
+        model.llm_get_command(config, prompt).map(|suggestion| suggestion.into_iter().collect())
+    }
+}
+
+/// Lists candidate commands and asks which one to use. Returns `None` if the user cancels.
+fn pick_suggestion(suggestions: &[Suggestion]) -> Result<Option<usize>, io::Error> {
+    println!("{}", "Candidate commands:".cyan());
+    for (idx, suggestion) in suggestions.iter().enumerate() {
+        println!(" {}. {}", idx + 1, suggestion.command.cyan().bold());
+        if let Some(details) = &suggestion.details {
+            if !details.explanation.is_empty() {
+                println!("    {}", details.explanation.italic());
+            }
+        }
+    }
+
+    read_choice(suggestions.len())
+}
+
+/// Asks for a number from 1 to `count`. Returns the zero-based index, or `None` if the user cancels.
+fn read_choice(count: usize) -> Result<Option<usize>, io::Error> {
+    loop {
+        print!("{}", format!("Select a command (1-{}, empty to cancel): ", count).cyan());
+        io::stdout().flush()?;
+        let mut choice = String::new();
+        io::stdin().read_line(&mut choice)?;
+        let choice = choice.trim();
+
+        if choice.is_empty() {
+            return Ok(None);
+        }
+        match choice.parse::<usize>() {
+            Ok(num) if num > 0 && num <= count => return Ok(Some(num - 1)),
+            _ => println!("{}", "Invalid selection. Please enter a valid number from the list.".red()),
+        }
+    }
+}
+
 fn execute_command(command: &str) -> Result<(), Box<dyn std::error::Error>> {
     let (shell_cmd, shell_arg) = Shell::detect().to_shell_command_and_command_arg();
 
-    match ProcessCommand::new(shell_cmd).arg(shell_arg).arg(&command).output() {
+    match ProcessCommand::new(shell_cmd).arg(shell_arg).arg(command).output() {
         Ok(output) => {
             println!("{}", "Command output:".green().bold());
             io::stdout().write_all(&output.stdout)?;
@@ -1,91 +1,345 @@
-use openai_api_rust::{Auth, Message, OpenAI, Role};
-use openai_api_rust::chat::{ChatApi, ChatBody};
 use serde::{Deserialize, Serialize};
-use std::env;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
 use crate::Config;
+use crate::openrouter_client::ProviderPreferences;
+use crate::provider::{AnthropicProvider, AzureOpenAiProvider, ChatRequest, Completion, GeminiProvider, LocalProvider, OllamaProvider, OpenAiCompatibleProvider, OpenAiProvider, OpenRouterProvider, Provider};
+use crate::sanitize::sanitize_command;
 use crate::shell::Shell;
+use crate::structured::{self, CommandDetails};
+use crate::usage::{self, Usage};
+
+/// A command the model proposed, plus whatever it said about it.
+pub struct Suggestion {
+    pub command: String,
+    pub details: Option<CommandDetails>,
+    /// How sure the model was of the answer, from its token log probabilities.
+    pub confidence: Option<f64>,
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(remote = "Self")]
 pub enum Model {
-    #[serde(rename = "gpt-4o")]
-    OpenAiGpt4o,
-
-    #[serde(rename = "gpt-4o-mini")]
-    OpenAiGpt4oMini,
+    /// Any OpenAI chat model, e.g. `gpt-4o-mini`.
+    #[serde(rename = "openai")]
+    OpenAi { model_name: String },
 
     #[serde(rename = "ollama")]
     Ollama(String),
 
     #[serde(rename = "openrouter")]
-    OpenRouter { model_name: String },
+    OpenRouter {
+        model_name: String,
+        /// Provider routing preferences sent with every request.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        provider: Option<ProviderPreferences>,
+        /// Server-side fallbacks, tried by OpenRouter when `model_name` is unavailable.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        models: Vec<String>,
+    },
+
+    #[serde(rename = "anthropic")]
+    Anthropic { model_name: String },
+
+    #[serde(rename = "gemini")]
+    Gemini { model_name: String },
+
+    #[serde(rename = "azure")]
+    AzureOpenAi {
+        endpoint: String,
+        deployment: String,
+        api_version: String,
+        /// The OpenAI model behind the deployment, which decides how requests are shaped.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        model: Option<String>,
+    },
+
+    #[serde(rename = "openai-compatible")]
+    OpenAiCompatible {
+        base_url: String,
+        model_name: String,
+        #[serde(default)]
+        api_key_env: Option<String>,
+        #[serde(default)]
+        extra_headers: HashMap<String, String>,
+    },
+
+    /// A GGUF model run in-process on the CPU (`local` cargo feature).
+    #[serde(rename = "local")]
+    Local {
+        gguf_path: PathBuf,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tokenizer_path: Option<PathBuf>,
+    },
 }
 
-impl Model {
-    pub fn llm_get_command(&self, config: &Config, user_prompt: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
-        let model_name_for_api = self.get_api_model_name();
-        let auth = self.get_auth()?;
-        let client = OpenAI::new(auth, self.get_api_endpoint().as_str());
+// The derived impls are reached through `Model::serialize`/`Model::deserialize`
+// (`remote = "Self"`), so reading can also accept what older versions wrote.
+impl Serialize for Model {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Model::serialize(self, serializer)
+    }
+}
 
-        let shell = Shell::detect();
-        let system_prompt = self.get_system_prompt(&shell);
-
-        let body = ChatBody {
-            model: model_name_for_api,
-            max_tokens: Some(config.max_tokens),
-            temperature: Some(0.5),
-            top_p: None,
-            n: None,
-            stream: None,
-            stop: None,
-            presence_penalty: None,
-            frequency_penalty: None,
-            logit_bias: None,
-            user: None,
-            messages: vec![
-                Message { role: Role::System, content: system_prompt.to_string() },
-                Message { role: Role::User, content: user_prompt.to_string() }
-            ],
+impl<'de> Deserialize<'de> for Model {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        match value.as_str() {
+            // Before any OpenAI model could be chosen, these two were unit variants.
+            Some(model_name @ ("gpt-4o" | "gpt-4o-mini")) => Ok(Model::OpenAi { model_name: model_name.to_string() }),
+            _ => Model::deserialize(value).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// Short `provider:model` form used in messages, e.g. `ollama:llama3.1`.
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Model::OpenAi { model_name } => write!(f, "openai:{}", model_name),
+            Model::Ollama(model_name) => write!(f, "ollama:{}", model_name),
+            Model::OpenRouter { model_name, .. } => write!(f, "openrouter:{}", model_name),
+            Model::Anthropic { model_name } => write!(f, "anthropic:{}", model_name),
+            Model::Gemini { model_name } => write!(f, "gemini:{}", model_name),
+            Model::AzureOpenAi { deployment, .. } => write!(f, "azure:{}", deployment),
+            Model::OpenAiCompatible { model_name, .. } => write!(f, "openai-compatible:{}", model_name),
+            Model::Local { gguf_path, .. } => write!(f, "local:{}", gguf_path.display()),
+        }
+    }
+}
+
+/// The provider prefixes of the `provider:name` form.
+pub const PROVIDER_NAMES: &[&str] = &["openai", "ollama", "openrouter", "anthropic", "gemini", "azure", "openai-compatible", "local"];
+
+/// Azure OpenAI api-version used when none is configured.
+pub const AZURE_DEFAULT_API_VERSION: &str = "2024-10-21";
+
+/// Splits `provider:name` into its parts. Anything without a known provider
+/// prefix is a bare name, since Ollama tags contain colons too (`qwen2.5-coder:7b`).
+fn split_provider(spec: &str) -> (Option<&str>, &str) {
+    match spec.split_once(':') {
+        Some((provider, name)) if PROVIDER_NAMES.contains(&provider) => (Some(provider), name),
+        _ => (None, spec),
+    }
+}
+
+/// Reads the `provider:name` form that `Display` writes. Azure takes its
+/// endpoint from `AZURE_OPENAI_ENDPOINT`; an OpenAI-compatible server's URL
+/// can't be written this way, so it has to come from the config file.
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (Some(provider), name) = split_provider(spec) else {
+            return Err(format!("'{}' doesn't start with a provider (one of {}), as in ollama:qwen2.5-coder", spec, PROVIDER_NAMES.join(", ")));
         };
+        if name.is_empty() {
+            return Err(format!("'{}' doesn't name a model", spec));
+        }
+        let model_name = name.to_string();
 
-        match client.chat_completion_create(&body) {
-            Ok(response) => Ok(response.choices.first()
-                .map(|choice| choice.message.as_ref())
-                .flatten()
-                .map(|message| message.content.clone())
-            ),
-            Err(e) => Err(format!("API Error for model {:?}: {:?}", self, e).into()),
+        match provider {
+            "openai" => Ok(Model::OpenAi { model_name }),
+            "ollama" => Ok(Model::Ollama(model_name)),
+            "openrouter" => Ok(Model::OpenRouter {
+                model_name,
+                provider: Some(ProviderPreferences::deny_data_collection()),
+                models: Vec::new(),
+            }),
+            "anthropic" => Ok(Model::Anthropic { model_name }),
+            "gemini" => Ok(Model::Gemini { model_name }),
+            "azure" => {
+                let endpoint = std::env::var("AZURE_OPENAI_ENDPOINT")
+                    .map_err(|_| format!("'{}' needs the resource endpoint in AZURE_OPENAI_ENDPOINT", spec))?;
+                Ok(Model::AzureOpenAi { endpoint, deployment: model_name, api_version: AZURE_DEFAULT_API_VERSION.to_string(), model: None })
+            }
+            "openai-compatible" => Err(format!("'{}' needs the server's base URL; configure an OpenAI-compatible server with --config first", spec)),
+            "local" => Ok(Model::Local { gguf_path: PathBuf::from(model_name), tokenizer_path: None }),
+            _ => unreachable!("split_provider only returns known providers"),
         }
     }
+}
 
-    fn get_api_model_name(&self) -> String {
+impl Model {
+    /// The provider prefix this model is written with.
+    pub fn provider_name(&self) -> &'static str {
         match self {
-            Model::OpenAiGpt4o => "gpt-4o".to_string(),
-            Model::OpenAiGpt4oMini => "gpt-4o-mini".to_string(),
-            Model::Ollama(model_name) => model_name.to_string(),
-            Model::OpenRouter { model_name } => model_name.clone(),
+            Model::OpenAi { .. } => "openai",
+            Model::Ollama(_) => "ollama",
+            Model::OpenRouter { .. } => "openrouter",
+            Model::Anthropic { .. } => "anthropic",
+            Model::Gemini { .. } => "gemini",
+            Model::AzureOpenAi { .. } => "azure",
+            Model::OpenAiCompatible { .. } => "openai-compatible",
+            Model::Local { .. } => "local",
         }
     }
 
-    fn get_api_endpoint(&self) -> String {
-        match self {
-            Model::OpenAiGpt4o | Model::OpenAiGpt4oMini => "https://api.openai.com/v1/".to_string(),
-            Model::Ollama(_) => "http://localhost:11434/v1/".to_string(),
-            Model::OpenRouter { .. } => "https://openrouter.ai/api/v1".to_string(),
+    /// The model `spec` picks for a single run instead of this one. `spec` is
+    /// `provider:name`, or just a name for `provider` or else this model's
+    /// provider. A model from the same provider keeps this one's endpoint,
+    /// headers and routing preferences.
+    pub fn overridden_by(&self, spec: &str, provider: Option<&str>) -> Result<Model, String> {
+        let (prefix, name) = split_provider(spec);
+        if let (Some(prefix), Some(provider)) = (prefix, provider) {
+            if prefix != provider {
+                return Err(format!("--model {} is for {}, but --provider is {}", spec, prefix, provider));
+            }
         }
+        let provider = prefix.or(provider).unwrap_or(self.provider_name());
+        if name.is_empty() {
+            return Err(format!("'{}' doesn't name a model", spec));
+        }
+        if provider != self.provider_name() {
+            return format!("{}:{}", provider, name).parse();
+        }
+
+        let mut model = self.clone();
+        match &mut model {
+            Model::OpenAi { model_name }
+            | Model::Ollama(model_name)
+            | Model::OpenRouter { model_name, .. }
+            | Model::Anthropic { model_name }
+            | Model::Gemini { model_name }
+            | Model::OpenAiCompatible { model_name, .. } => *model_name = name.to_string(),
+            // The configured underlying model belongs to the configured deployment.
+            Model::AzureOpenAi { deployment, model: underlying, .. } => {
+                *deployment = name.to_string();
+                *underlying = None;
+            }
+            // The configured tokenizer belongs to the configured model.
+            Model::Local { .. } => model = Model::Local { gguf_path: PathBuf::from(name), tokenizer_path: None },
+        }
+        Ok(model)
     }
 
-    fn get_auth(&self) -> Result<Auth, Box<dyn std::error::Error>> {
-        match self {
-            Model::OpenAiGpt4o | Model::OpenAiGpt4oMini => {
-                Ok(Auth::from_env().map_err(|_| "OPENAI_API_KEY environment variable not set or invalid".to_string())?)
+    pub fn llm_get_command(&self, config: &Config, user_prompt: &str) -> Result<Option<Suggestion>, Box<dyn std::error::Error>> {
+        let completion = self.with_request(config, user_prompt, |request| self.provider().complete(request))?;
+        self.record_usage(completion.iter());
+        match completion {
+            Some(completion) => parse_suggestion(&completion, config.structured),
+            None => Ok(None),
+        }
+    }
+
+    /// Like `llm_get_command`, but hands each piece of the answer to `on_token` as it streams in.
+    pub fn llm_stream_command(&self, config: &Config, user_prompt: &str, on_token: &mut dyn FnMut(&str)) -> Result<Option<Suggestion>, Box<dyn std::error::Error>> {
+        let completion = self.with_request(config, user_prompt, |request| self.provider().complete_streaming(request, on_token))?;
+        self.record_usage(completion.iter());
+        match completion {
+            Some(completion) => parse_suggestion(&completion, config.structured),
+            None => Ok(None),
+        }
+    }
+
+    /// Asks for `n` alternative commands. Answers that sanitize to nothing or
+    /// to a duplicate are dropped; if none are usable, the first sanitizer
+    /// error is returned.
+    pub fn llm_get_candidates(&self, config: &Config, user_prompt: &str, n: u32) -> Result<Vec<Suggestion>, Box<dyn std::error::Error>> {
+        let answers = self.with_request(config, user_prompt, |request| self.provider().complete_many(request, n))?;
+        self.record_usage(answers.iter());
+
+        let mut suggestions: Vec<Suggestion> = Vec::new();
+        let mut first_error = None;
+        for completion in answers {
+            match parse_suggestion(&completion, config.structured) {
+                Ok(Some(suggestion)) => {
+                    if !suggestions.iter().any(|existing| existing.command == suggestion.command) {
+                        suggestions.push(suggestion);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
             }
-            Model::Ollama(_) => Ok(Auth::new("ollama")),
-            Model::OpenRouter { .. } => {
-                let api_key = env::var("OPENROUTER_API_KEY")
-                    .map_err(|_| "OPENROUTER_API_KEY environment variable not set".to_string())?;
-                Ok(Auth::new(&api_key))
+        }
+
+        match first_error {
+            Some(e) if suggestions.is_empty() => Err(e),
+            _ => Ok(suggestions),
+        }
+    }
+
+    /// Adds up the tokens the answers used and writes them to the usage ledger.
+    fn record_usage<'a>(&self, completions: impl Iterator<Item = &'a Completion>) {
+        let mut total: Option<Usage> = None;
+        let mut id = None;
+        let mut served_by = None;
+        for completion in completions {
+            if let Some(usage) = &completion.usage {
+                total.get_or_insert_with(Usage::default).add(usage);
+            }
+            if id.is_none() {
+                id = completion.id.clone();
+            }
+            if served_by.is_none() {
+                served_by = completion.model.clone();
             }
         }
+        if let Some(total) = total {
+            usage::record(self, total, id, served_by.as_deref());
+        }
+    }
+
+    /// Builds the request for this model and the current settings and hands it to `send`.
+    fn with_request<T>(&self, config: &Config, user_prompt: &str, send: impl FnOnce(&ChatRequest) -> T) -> T {
+        let shell = Shell::detect();
+        let mut system_prompt = self.get_system_prompt(&shell);
+        let schema = config.structured.then(structured::response_schema);
+        if schema.is_some() {
+            system_prompt.push_str(structured::prompt_suffix());
+        }
+
+        let request = ChatRequest {
+            system_prompt: &system_prompt,
+            user_prompt,
+            max_tokens: config.max_tokens,
+            response_schema: schema.as_ref(),
+            logprobs: config.escalation.is_some(),
+            sampling: config.sampling_for(self),
+        };
+
+        send(&request)
+    }
+
+    /// Whether requests to this model may cost money. Ollama and GGUF models run locally, and
+    /// an OpenAI-compatible server without an API key is taken to be self-hosted.
+    pub fn is_paid(&self) -> bool {
+        !matches!(self, Model::Ollama(_) | Model::Local { .. } | Model::OpenAiCompatible { api_key_env: None, .. })
+    }
+
+    /// Selects and configures the backend that serves this model.
+    pub fn provider(&self) -> Box<dyn Provider> {
+        match self {
+            Model::OpenAi { model_name } => Box::new(OpenAiProvider { model_name: model_name.clone() }),
+            Model::Ollama(model_name) => Box::new(OllamaProvider { model_name: model_name.clone() }),
+            Model::OpenRouter { model_name, provider, models } => Box::new(OpenRouterProvider {
+                model_name: model_name.clone(),
+                provider: provider.clone(),
+                models: models.clone(),
+            }),
+            Model::Anthropic { model_name } => Box::new(AnthropicProvider { model_name: model_name.clone() }),
+            Model::Gemini { model_name } => Box::new(GeminiProvider { model_name: model_name.clone() }),
+            Model::AzureOpenAi { endpoint, deployment, api_version, model } => Box::new(AzureOpenAiProvider {
+                endpoint: endpoint.clone(),
+                deployment: deployment.clone(),
+                api_version: api_version.clone(),
+                model: model.clone(),
+            }),
+            Model::OpenAiCompatible { base_url, model_name, api_key_env, extra_headers } => Box::new(OpenAiCompatibleProvider {
+                base_url: base_url.clone(),
+                model_name: model_name.clone(),
+                api_key_env: api_key_env.clone(),
+                extra_headers: extra_headers.clone(),
+            }),
+            Model::Local { gguf_path, tokenizer_path } => Box::new(LocalProvider {
+                gguf_path: gguf_path.clone(),
+                tokenizer_path: tokenizer_path.clone(),
+            }),
+        }
     }
 
     fn get_system_prompt(&self, shell: &Shell) -> String {
@@ -110,3 +364,110 @@ impl Model {
         ", shell_command_type, std::env::consts::OS)
     }
 }
+
+/// Turns raw model output into a suggestion. Everything goes through the
+/// sanitizer, so callers only ever see a clean command, nothing, or an
+/// `UnusableOutput` error. In structured mode an answer that isn't valid JSON
+/// is still accepted as a plain command, just without details.
+fn parse_suggestion(completion: &Completion, structured: bool) -> Result<Option<Suggestion>, Box<dyn std::error::Error>> {
+    let confidence = completion.confidence;
+    if structured {
+        if let Some((command, details)) = structured::parse_structured(&completion.text) {
+            return Ok(sanitize_command(&command)?.map(|command| Suggestion { command, details: Some(details), confidence }));
+        }
+    }
+
+    Ok(sanitize_command(&completion.text)?.map(|command| Suggestion { command, details: None, confidence }))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use super::{split_provider, Model};
+
+    #[test]
+    fn reads_legacy_and_tagged_openai_models() {
+        let cases = [
+            (json!("gpt-4o"), "openai:gpt-4o"),
+            (json!("gpt-4o-mini"), "openai:gpt-4o-mini"),
+            (json!({ "openai": { "model_name": "o3-mini" } }), "openai:o3-mini"),
+            (json!({ "ollama": "qwen2.5-coder:7b" }), "ollama:qwen2.5-coder:7b"),
+        ];
+        for (stored, expected) in cases {
+            let model: Model = serde_json::from_value(stored.clone()).unwrap();
+            assert_eq!(model.to_string(), expected, "{}", stored);
+        }
+
+        assert!(serde_json::from_value::<Model>(json!("gpt-3.5-turbo")).is_err());
+    }
+
+    #[test]
+    fn writes_legacy_models_in_the_tagged_form() {
+        let model: Model = serde_json::from_value(json!("gpt-4o-mini")).unwrap();
+        assert_eq!(serde_json::to_value(&model).unwrap(), json!({ "openai": { "model_name": "gpt-4o-mini" } }));
+    }
+
+    #[test]
+    fn splits_off_known_provider_prefixes() {
+        let cases = [
+            ("ollama:qwen2.5-coder:7b", (Some("ollama"), "qwen2.5-coder:7b")),
+            ("qwen2.5-coder:7b", (None, "qwen2.5-coder:7b")),
+            ("gpt-4o", (None, "gpt-4o")),
+            ("openrouter:meta-llama/llama-3.1-70b-instruct", (Some("openrouter"), "meta-llama/llama-3.1-70b-instruct")),
+            ("openai-compatible:mock-model", (Some("openai-compatible"), "mock-model")),
+            ("local:/opt/models/model.gguf", (Some("local"), "/opt/models/model.gguf")),
+            ("ollama:", (Some("ollama"), "")),
+        ];
+        for (spec, expected) in cases {
+            assert_eq!(split_provider(spec), expected, "{}", spec);
+        }
+    }
+
+    #[test]
+    fn overrides_the_configured_model() {
+        let openrouter = json!({ "openrouter": {
+            "model_name": "meta-llama/llama-3.1-70b-instruct",
+            "provider": { "order": ["Together"] },
+            "models": ["mistralai/mistral-7b-instruct"],
+        } });
+        let azure = json!({ "azure": {
+            "endpoint": "https://my-resource.openai.azure.com",
+            "deployment": "prod",
+            "api_version": "2024-10-21",
+            "model": "o3-mini",
+        } });
+        let compatible = json!({ "openai-compatible": { "base_url": "http://gpu-box:8000/v1", "model_name": "a", "extra_headers": {} } });
+        let local = json!({ "local": { "gguf_path": "/opt/a.gguf", "tokenizer_path": "/opt/tokenizer.json" } });
+        let openai = json!({ "openai": { "model_name": "gpt-4o-mini" } });
+
+        let cases: [(&Value, &str, Option<&str>, Option<Value>); 10] = [
+            // Same provider: endpoint, headers and routing preferences are kept.
+            (&openrouter, "qwen/qwen-2.5-coder-32b-instruct", None, Some(json!({ "openrouter": {
+                "model_name": "qwen/qwen-2.5-coder-32b-instruct",
+                "provider": { "order": ["Together"] },
+                "models": ["mistralai/mistral-7b-instruct"],
+            } }))),
+            (&compatible, "openai-compatible:b", None, Some(json!({ "openai-compatible": {
+                "base_url": "http://gpu-box:8000/v1", "model_name": "b", "api_key_env": null, "extra_headers": {},
+            } }))),
+            // ...except what belongs to the configured model alone.
+            (&azure, "staging", None, Some(json!({ "azure": {
+                "endpoint": "https://my-resource.openai.azure.com", "deployment": "staging", "api_version": "2024-10-21",
+            } }))),
+            (&local, "/opt/b.gguf", None, Some(json!({ "local": { "gguf_path": "/opt/b.gguf" } }))),
+            // Another provider, by prefix or by --provider.
+            (&openai, "ollama:llama3.1:8b", None, Some(json!({ "ollama": "llama3.1:8b" }))),
+            (&openai, "llama3.1:8b", Some("ollama"), Some(json!({ "ollama": "llama3.1:8b" }))),
+            (&openai, "ollama:llama3.1", Some("ollama"), Some(json!({ "ollama": "llama3.1" }))),
+            // A conflicting --provider, a missing name, or a server that needs its URL.
+            (&openai, "ollama:llama3.1", Some("openai"), None),
+            (&openai, "ollama:", None, None),
+            (&openai, "openai-compatible:b", None, None),
+        ];
+        for (configured, spec, provider, expected) in cases {
+            let configured: Model = serde_json::from_value(configured.clone()).unwrap();
+            let overridden = configured.overridden_by(spec, provider).ok().map(|model| serde_json::to_value(model).unwrap());
+            assert_eq!(overridden, expected, "{} with --provider {:?}", spec, provider);
+        }
+    }
+}
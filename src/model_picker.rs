@@ -0,0 +1,536 @@
+use colored::*;
+use std::io::{self, Write};
+use crate::openrouter_client::OpenRouterModel;
+use crate::provider::ollama::{self, InstalledModel, PullProgress};
+use crate::provider::openai::{self, OpenAiProvider};
+use crate::provider::Provider;
+
+// Interactive model pickers for `--config`. OpenAI's chat models are listed
+// from the API with their context windows. The OpenRouter catalogue has
+// hundreds of entries, so instead of numbering all of them the user narrows
+// it down with a query and picks from the best matches. Ollama lists what is
+// installed and can pull what isn't.
+
+/// Matches shown per search.
+const SHOWN: usize = 20;
+
+/// A parsed search line, e.g. `llama ctx:32k price:1 input:image`.
+#[derive(Default)]
+struct Query {
+    words: Vec<String>,
+    min_context: Option<i64>,
+    /// US dollars per million tokens, for both prompt and completion.
+    max_price: Option<f64>,
+    input: Option<String>,
+}
+
+impl Query {
+    fn parse(line: &str) -> Result<Self, String> {
+        let mut query = Query::default();
+        for term in line.split_whitespace() {
+            match term.split_once(':') {
+                Some(("ctx", value)) => {
+                    query.min_context = Some(parse_tokens(value).ok_or_else(|| format!("Invalid context length '{}'", value))?);
+                }
+                Some(("price", value)) => {
+                    query.max_price = Some(value.trim_start_matches('$').parse().map_err(|_| format!("Invalid price '{}'", value))?);
+                }
+                Some(("input", value)) => query.input = Some(value.to_lowercase()),
+                _ => query.words.push(term.to_lowercase()),
+            }
+        }
+        Ok(query)
+    }
+
+    fn admits(&self, model: &OpenRouterModel) -> bool {
+        if let Some(min_context) = self.min_context {
+            if i64::from(model.context_length.unwrap_or(0)) < min_context {
+                return false;
+            }
+        }
+        if let Some(max_price) = self.max_price {
+            // Models without published prices can't be shown to be cheap enough.
+            let Some((prompt, completion)) = model.pricing.as_ref().and_then(|pricing| pricing.per_million()) else {
+                return false;
+            };
+            if prompt > max_price || completion > max_price {
+                return false;
+            }
+        }
+        if let Some(input) = &self.input {
+            if !model.accepts_input(input) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// How well the words match the id and name; `None` if one doesn't match
+    /// at all. Lower is better: substrings rank above scattered characters.
+    fn score(&self, model: &OpenRouterModel) -> Option<usize> {
+        let id = model.id.to_lowercase();
+        let name = model.name.to_lowercase();
+        let mut score = 0;
+        for word in &self.words {
+            score += if id.contains(word.as_str()) || name.contains(word.as_str()) {
+                0
+            } else {
+                subsequence_gaps(word, &id)? + 100
+            };
+        }
+        Some(score)
+    }
+}
+
+/// Parses `32000`, `32k` or `1m`.
+fn parse_tokens(value: &str) -> Option<i64> {
+    let value = value.to_lowercase();
+    let (digits, multiplier) = match value.strip_suffix('k') {
+        Some(digits) => (digits, 1_000.0),
+        None => match value.strip_suffix('m') {
+            Some(digits) => (digits, 1_000_000.0),
+            None => (value.as_str(), 1.0),
+        },
+    };
+    digits.parse::<f64>().ok().map(|number| (number * multiplier) as i64)
+}
+
+/// Whether the characters of `word` appear in order in `text`, and if so how
+/// many characters lie between the first and last of them beyond the word itself.
+fn subsequence_gaps(word: &str, text: &str) -> Option<usize> {
+    let mut chars = word.chars();
+    let mut next = chars.next()?;
+    let mut start = None;
+    for (idx, c) in text.chars().enumerate() {
+        if c == next {
+            start.get_or_insert(idx);
+            match chars.next() {
+                Some(following) => next = following,
+                None => return Some(idx + 1 - start.unwrap_or(idx) - word.chars().count()),
+            }
+        }
+    }
+    None
+}
+
+/// The models the query admits and matches, best first; ties go to the
+/// shorter id, then alphabetically.
+fn search<'a>(models: &'a [OpenRouterModel], query: &Query) -> Vec<&'a OpenRouterModel> {
+    let mut scored: Vec<(usize, &OpenRouterModel)> = models.iter()
+        .filter(|model| query.admits(model))
+        .filter_map(|model| query.score(model).map(|score| (score, model)))
+        .collect();
+    scored.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.id.len().cmp(&b.1.id.len())).then_with(|| a.1.id.cmp(&b.1.id)));
+    scored.into_iter().map(|(_, model)| model).collect()
+}
+
+/// Lets the user search `models` and pick one. Returns `None` if they leave
+/// the picker to enter an id by hand.
+pub fn pick_openrouter_model(models: &[OpenRouterModel]) -> Result<Option<OpenRouterModel>, io::Error> {
+    println!("{}", format!("{} OpenRouter models available. Search by id or name; narrow down with", models.len()).cyan());
+    println!("{}", "  ctx:32k (minimum context), price:1 (max $ per million tokens), input:image".cyan());
+    println!("{}", "Enter a number from the results to choose it, or an empty line to type an id yourself.".cyan());
+
+    let mut results: Vec<&OpenRouterModel> = Vec::new();
+    loop {
+        print!("{}", "Search: ".cyan());
+        io::stdout().flush()?;
+        let mut line = String::new();
+        io::stdin().read_line(&mut line)?;
+        let line = line.trim();
+
+        if line.is_empty() {
+            return Ok(None);
+        }
+        if let Ok(num) = line.parse::<usize>() {
+            if num > 0 && num <= results.len().min(SHOWN) {
+                let chosen = results[num - 1];
+                if confirm(chosen)? {
+                    return Ok(Some(chosen.clone()));
+                }
+                continue;
+            }
+        }
+
+        let query = match Query::parse(line) {
+            Ok(query) => query,
+            Err(e) => {
+                println!("{}", e.red());
+                continue;
+            }
+        };
+        results = search(models, &query);
+
+        if results.is_empty() {
+            println!("{}", "No models match. Try a shorter search or looser filters.".yellow());
+            continue;
+        }
+        for (idx, model) in results.iter().take(SHOWN).enumerate() {
+            println!(" {}. {}", idx + 1, summary(model));
+        }
+        if results.len() > SHOWN {
+            println!("{}", format!(" ... and {} more; refine the search to see them.", results.len() - SHOWN).dimmed());
+        }
+    }
+}
+
+/// One line per model: id, context, prices and non-text inputs.
+fn summary(model: &OpenRouterModel) -> String {
+    let mut line = format!("{} (Context: {} tokens", model.id, model.context_length.unwrap_or(0));
+    match model.pricing.as_ref().map(|pricing| pricing.per_million()) {
+        Some(Some((prompt, completion))) if prompt == 0.0 && completion == 0.0 => line.push_str(", free"),
+        Some(Some((prompt, completion))) => line.push_str(&format!(", ${:.2}/${:.2} per M tokens", prompt, completion)),
+        Some(None) => line.push_str(", variable price"),
+        None => {}
+    }
+    line.push(')');
+    let extra_inputs: Vec<&str> = ["image", "audio", "file"].into_iter().filter(|input| model.accepts_input(input)).collect();
+    if !extra_inputs.is_empty() {
+        line.push_str(&format!(" [{}]", extra_inputs.join(", ")).dimmed().to_string());
+    }
+    line
+}
+
+/// Shows the model's name and description and asks whether to use it.
+fn confirm(model: &OpenRouterModel) -> Result<bool, io::Error> {
+    println!("{}", model.id.bold());
+    if !model.name.is_empty() {
+        println!("{}", model.name);
+    }
+    if !model.description.is_empty() {
+        let description: String = model.description.chars().take(400).collect();
+        let ellipsis = if model.description.chars().count() > 400 { "..." } else { "" };
+        println!("{}", format!("{}{}", description, ellipsis).dimmed());
+    }
+    print!("{}", "Use this model? (Y/n): ".cyan());
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(!answer.trim().eq_ignore_ascii_case("n"))
+}
+
+/// Default for OpenAI, and the model offered when the list can't be fetched.
+const DEFAULT_OPENAI_MODEL: &str = "gpt-4o-mini";
+
+/// Lists the chat models the API key can use and lets the user pick one or
+/// name another. Returns `None` to go back to provider selection.
+pub fn pick_openai_model() -> Result<Option<String>, io::Error> {
+    let probe = OpenAiProvider { model_name: String::new() };
+    if let Err(e) = probe.api_key() {
+        println!("{}", e.to_string().red());
+        println!("{}", "Please set it and try again, or choose another provider.".yellow());
+        return Ok(None);
+    }
+
+    let mut models = match probe.list_models() {
+        Ok(models) => models,
+        Err(e) => {
+            println!("{}", format!("Could not list OpenAI models: {}", e).yellow());
+            Vec::new()
+        }
+    };
+    models.retain(|model| openai::is_chat_model(model));
+    models.sort();
+
+    if !models.is_empty() {
+        println!("{}", "OpenAI chat models:".cyan());
+        for (idx, model) in models.iter().enumerate() {
+            let context_length = openai::context_length(model).unwrap_or(0);
+            let max_output_tokens = openai::max_output_tokens(model).unwrap_or(0);
+            println!(" {}. {} (Context: {} tokens, output: {})", idx + 1, model, context_length, max_output_tokens);
+        }
+    }
+
+    print!("{}", format!("Enter a number or an OpenAI model id (default: {}): ", DEFAULT_OPENAI_MODEL).cyan());
+    io::stdout().flush()?;
+    let mut choice = String::new();
+    io::stdin().read_line(&mut choice)?;
+    let choice = choice.trim();
+
+    if let Ok(num) = choice.parse::<usize>() {
+        if num > 0 && num <= models.len() {
+            return Ok(Some(models[num - 1].clone()));
+        }
+    }
+    Ok(Some(if choice.is_empty() { DEFAULT_OPENAI_MODEL.to_string() } else { choice.to_string() }))
+}
+
+/// Default when nothing is installed.
+const DEFAULT_OLLAMA_MODEL: &str = "llama3.1";
+
+/// Lists the models installed in Ollama and lets the user pick one or name
+/// another, offering to pull it if it isn't installed. Returns `None` to go
+/// back to provider selection.
+pub fn pick_ollama_model() -> Result<Option<String>, io::Error> {
+    let installed = match ollama::installed_models() {
+        Ok(installed) => Some(installed),
+        Err(e) => {
+            println!("{}", e.to_string().red());
+            println!("{}", "You can still enter a model name; it has to be pulled before it can be used.".yellow());
+            None
+        }
+    };
+
+    let default_name = installed.as_ref()
+        .and_then(|installed| installed.first())
+        .map_or(DEFAULT_OLLAMA_MODEL.to_string(), |model| model.name.clone());
+    match installed.as_deref() {
+        Some([]) => println!("{}", "No Ollama models are installed yet.".yellow()),
+        Some(models) => {
+            println!("{}", "Installed Ollama models:".cyan());
+            for (idx, model) in models.iter().enumerate() {
+                println!(" {}. {}", idx + 1, describe_installed(model));
+            }
+        }
+        None => {}
+    }
+
+    print!("{}", format!("Enter a number or an Ollama model name (default: {}): ", default_name).cyan());
+    io::stdout().flush()?;
+    let mut choice = String::new();
+    io::stdin().read_line(&mut choice)?;
+    let choice = choice.trim();
+
+    // Without a daemon to ask, there is nothing to check against or pull into.
+    let reachable = installed.is_some();
+    let installed = installed.unwrap_or_default();
+    if let Ok(num) = choice.parse::<usize>() {
+        if num > 0 && num <= installed.len() {
+            return Ok(Some(installed[num - 1].name.clone()));
+        }
+    }
+    let model_name = if choice.is_empty() { default_name } else { choice.to_string() };
+
+    // Ollama reports untagged models as `name:latest`.
+    let is_installed = installed.iter()
+        .any(|model| model.name == model_name || model.name == format!("{}:latest", model_name));
+    if is_installed || !reachable {
+        return Ok(Some(model_name));
+    }
+
+    print!("{}", format!("{} isn't installed. Pull it now? (Y/n): ", model_name).cyan());
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    if answer.trim().eq_ignore_ascii_case("n") {
+        return Ok(Some(model_name));
+    }
+
+    let mut bar = ProgressBar::default();
+    let pulled = ollama::pull_model(&model_name, |progress| bar.update(progress));
+    bar.finish();
+    match pulled {
+        Ok(()) => {
+            println!("{}", format!("Pulled {}.", model_name).green());
+            Ok(Some(model_name))
+        }
+        Err(e) => {
+            println!("{}", e.to_string().red());
+            Ok(None)
+        }
+    }
+}
+
+/// Name, parameter count, quantization and size on disk.
+fn describe_installed(model: &InstalledModel) -> String {
+    let details: Vec<String> = [model.details.parameter_size.clone(), model.details.quantization_level.clone()]
+        .into_iter()
+        .flatten()
+        .chain((model.size > 0).then(|| format_bytes(model.size)))
+        .collect();
+    if details.is_empty() {
+        model.name.clone()
+    } else {
+        format!("{} ({})", model.name, details.join(", "))
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64;
+    let mut unit = "";
+    for next in UNITS {
+        if value < 1000.0 {
+            break;
+        }
+        value /= 1000.0;
+        unit = next;
+    }
+    format!("{:.1} {}", value, unit)
+}
+
+/// Redraws one line per pull status, with a bar while a layer downloads.
+#[derive(Default)]
+struct ProgressBar {
+    status: String,
+}
+
+impl ProgressBar {
+    const WIDTH: usize = 30;
+
+    fn update(&mut self, progress: &PullProgress) {
+        if progress.status != self.status {
+            if !self.status.is_empty() {
+                println!();
+            }
+            self.status = progress.status.clone();
+        }
+
+        let line = match (progress.completed, progress.total) {
+            (completed, Some(total)) if total > 0 => {
+                let completed = completed.unwrap_or(0).min(total);
+                let filled = (completed as f64 / total as f64 * Self::WIDTH as f64) as usize;
+                format!(
+                    "{} [{}{}] {:>3}% {}/{}",
+                    short_status(&progress.status),
+                    "#".repeat(filled),
+                    " ".repeat(Self::WIDTH - filled),
+                    completed * 100 / total,
+                    format_bytes(completed),
+                    format_bytes(total),
+                )
+            }
+            _ => progress.status.clone(),
+        };
+        print!("\r{}\x1b[K", line);
+        let _ = io::stdout().flush();
+    }
+
+    fn finish(&self) {
+        if !self.status.is_empty() {
+            println!();
+        }
+    }
+}
+
+/// "pulling 6a0746a1ec1a" rather than the full digest Ollama sends.
+fn short_status(status: &str) -> String {
+    match status.strip_prefix("pulling sha256:") {
+        Some(digest) => format!("pulling {}", &digest[..digest.len().min(12)]),
+        None => status.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::openrouter_client::{OpenRouterArchitecture, OpenRouterModel, OpenRouterPricing};
+    use super::{parse_tokens, search, subsequence_gaps, Query};
+
+    /// The words, minimum context, maximum price and input of a query.
+    type Parsed = (&'static [&'static str], Option<i64>, Option<f64>, Option<&'static str>);
+
+    /// Search lines and what they parse into, or `None` if they are rejected.
+    const QUERIES: &[(&str, Option<Parsed>)] = &[
+        ("", Some((&[], None, None, None))),
+        ("Llama  Instruct", Some((&["llama", "instruct"], None, None, None))),
+        ("ctx:32000", Some((&[], Some(32_000), None, None))),
+        ("ctx:32k", Some((&[], Some(32_000), None, None))),
+        ("ctx:32K", Some((&[], Some(32_000), None, None))),
+        ("ctx:1.5k", Some((&[], Some(1_500), None, None))),
+        ("ctx:1m", Some((&[], Some(1_000_000), None, None))),
+        ("price:1", Some((&[], None, Some(1.0), None))),
+        ("price:$0.5", Some((&[], None, Some(0.5), None))),
+        ("input:Image", Some((&[], None, None, Some("image")))),
+        ("llama ctx:8k price:2 input:image", Some((&["llama"], Some(8_000), Some(2.0), Some("image")))),
+        ("vendor:meta", Some((&["vendor:meta"], None, None, None))),
+        ("ctx:", None),
+        ("ctx:k", None),
+        ("ctx:lots", None),
+        ("ctx:32kb", None),
+        ("price:", None),
+        ("price:cheap", None),
+        ("price:$", None),
+    ];
+
+    #[test]
+    fn parses_queries() {
+        for (line, expected) in QUERIES {
+            match (Query::parse(line), expected) {
+                (Ok(query), Some((words, min_context, max_price, input))) => {
+                    assert_eq!(query.words, *words, "{:?}", line);
+                    assert_eq!(query.min_context, *min_context, "{:?}", line);
+                    assert_eq!(query.max_price, *max_price, "{:?}", line);
+                    assert_eq!(query.input.as_deref(), *input, "{:?}", line);
+                }
+                (Err(_), None) => {}
+                (result, _) => panic!("{:?}: unexpected result {:?}", line, result.map(|query| query.words)),
+            }
+        }
+    }
+
+    #[test]
+    fn parses_token_counts() {
+        assert_eq!(parse_tokens("4096"), Some(4_096));
+        assert_eq!(parse_tokens("128k"), Some(128_000));
+        assert_eq!(parse_tokens("2M"), Some(2_000_000));
+        assert_eq!(parse_tokens(""), None);
+        assert_eq!(parse_tokens("m"), None);
+    }
+
+    #[test]
+    fn measures_subsequence_gaps() {
+        assert_eq!(subsequence_gaps("gpt", "openai/gpt-4o"), Some(0));
+        // Counted from the first matching character, not the closest run.
+        assert_eq!(subsequence_gaps("l3", "meta-llama/llama-3"), Some(11));
+        assert_eq!(subsequence_gaps("g4o", "openai/gpt-4o"), Some(3));
+        assert_eq!(subsequence_gaps("xyz", "meta-llama/llama-3"), None);
+        assert_eq!(subsequence_gaps("", "anything"), None);
+    }
+
+    fn model(id: &str, name: &str, context_length: i32, price: &str, inputs: &[&str]) -> OpenRouterModel {
+        OpenRouterModel {
+            id: id.to_string(),
+            context_length: Some(context_length),
+            pricing: Some(OpenRouterPricing { prompt: price.to_string(), completion: price.to_string(), request: String::new() }),
+            name: name.to_string(),
+            description: String::new(),
+            architecture: Some(OpenRouterArchitecture {
+                modality: None,
+                input_modalities: inputs.iter().map(|input| input.to_string()).collect(),
+            }),
+        }
+    }
+
+    /// Search lines and the ids they list, in order.
+    const SEARCHES: &[(&str, &[&str])] = &[
+        // Substring matches first, shorter ids before longer ones.
+        ("llama", &["meta-llama/llama-3-8b", "meta-llama/llama-3.1-405b", "meta-llama/llama-3.2-11b-vision"]),
+        // Names match too.
+        ("sonnet", &["anthropic/claude-3.5-sonnet"]),
+        // Scattered characters rank below substrings, closer ones first.
+        ("l38", &["meta-llama/llama-3-8b"]),
+        ("g4o", &["openai/gpt-4o", "openai/gpt-4o-mini"]),
+        ("gpt mini", &["openai/gpt-4o-mini"]),
+        ("mta11v", &["meta-llama/llama-3.2-11b-vision"]),
+        ("gpt", &["openai/gpt-4o", "openai/gpt-4o-mini"]),
+        ("ctx:100k", &["openai/gpt-4o", "openrouter/auto", "openai/gpt-4o-mini", "meta-llama/llama-3.1-405b", "anthropic/claude-3.5-sonnet"]),
+        ("llama price:1", &["meta-llama/llama-3-8b", "meta-llama/llama-3.2-11b-vision"]),
+        // Variable prices can't be shown to be cheap enough.
+        ("price:100", &["openai/gpt-4o", "openai/gpt-4o-mini", "meta-llama/llama-3-8b", "meta-llama/llama-3.1-405b", "anthropic/claude-3.5-sonnet", "meta-llama/llama-3.2-11b-vision"]),
+        ("input:image", &["openai/gpt-4o", "openrouter/auto", "openai/gpt-4o-mini", "anthropic/claude-3.5-sonnet", "meta-llama/llama-3.2-11b-vision"]),
+        ("llama input:image", &["meta-llama/llama-3.2-11b-vision"]),
+        ("qwen", &[]),
+    ];
+
+    #[test]
+    fn ranks_search_results() {
+        let models = [
+            model("meta-llama/llama-3.1-405b", "Meta: Llama 3.1 405B", 131_072, "0.000003", &["text"]),
+            model("openai/gpt-4o-mini", "OpenAI: GPT-4o-mini", 128_000, "0.0000006", &["text", "image"]),
+            model("meta-llama/llama-3-8b", "Meta: Llama 3 8B", 8_192, "0.0000001", &["text"]),
+            model("anthropic/claude-3.5-sonnet", "Anthropic: Claude 3.5 Sonnet", 200_000, "0.000015", &["text", "image"]),
+            model("meta-llama/llama-3.2-11b-vision", "Meta: Llama 3.2 11B Vision", 16_384, "0.00000005", &["text", "image"]),
+            model("openai/gpt-4o", "OpenAI: GPT-4o", 128_000, "0.00001", &["text", "image"]),
+            model("openrouter/auto", "Auto Router", 2_000_000, "-1", &["text", "image"]),
+        ];
+
+        for (line, expected) in SEARCHES {
+            let query = Query::parse(line).unwrap();
+            let ids: Vec<&str> = search(&models, &query).iter().map(|model| model.id.as_str()).collect();
+            assert_eq!(ids, *expected, "{:?}", line);
+        }
+    }
+}
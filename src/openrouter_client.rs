@@ -1,12 +1,82 @@
-use serde::Deserialize;
+use colored::*;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::http;
+use crate::usage;
 
 // Structs to represent the data from OpenRouter's /models endpoint
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct OpenRouterModel {
     pub id: String, // e.g., "mistralai/mistral-7b-instruct"
     #[serde(rename = "context_length")]
     pub context_length: Option<i32>, // Total context window size
-    // You can add more fields if needed, like description, pricing_info, etc.
+    pub pricing: Option<OpenRouterPricing>,
+    /// Display name, e.g. "Mistral: Mistral 7B Instruct".
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub architecture: Option<OpenRouterArchitecture>,
+}
+
+impl OpenRouterModel {
+    /// Whether the model accepts this kind of input, e.g. "image".
+    pub fn accepts_input(&self, modality: &str) -> bool {
+        let Some(architecture) = &self.architecture else {
+            return modality == "text";
+        };
+        if !architecture.input_modalities.is_empty() {
+            return architecture.input_modalities.iter().any(|input| input.eq_ignore_ascii_case(modality));
+        }
+        // Older entries only have the combined form, e.g. "text+image->text".
+        architecture.modality.as_deref()
+            .and_then(|combined| combined.split("->").next())
+            .is_some_and(|inputs| inputs.split('+').any(|input| input.eq_ignore_ascii_case(modality)))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OpenRouterArchitecture {
+    pub modality: Option<String>,
+    #[serde(default)]
+    pub input_modalities: Vec<String>,
+}
+
+/// US dollars per token (or per request), sent by OpenRouter as decimal strings.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OpenRouterPricing {
+    #[serde(default)]
+    pub prompt: String,
+    #[serde(default)]
+    pub completion: String,
+    #[serde(default)]
+    pub request: String,
+}
+
+impl OpenRouterPricing {
+    /// Prompt and completion prices in US dollars per million tokens, or
+    /// `None` if either is missing or variable.
+    pub fn per_million(&self) -> Option<(f64, f64)> {
+        Some((price(&self.prompt)? * 1_000_000.0, price(&self.completion)? * 1_000_000.0))
+    }
+
+    /// What a request cost, or `None` if a price it depends on is unknown.
+    pub fn cost(&self, prompt_tokens: u64, completion_tokens: u64) -> Option<f64> {
+        // Most models have no per-request fee and leave it out.
+        let request = if self.request.is_empty() { 0.0 } else { price(&self.request)? };
+        Some(price(&self.prompt)? * prompt_tokens as f64 + price(&self.completion)? * completion_tokens as f64 + request)
+    }
+}
+
+/// Parses one price. OpenRouter sends `"-1"` for models whose price varies,
+/// so negative prices are as unknown as ones that don't parse.
+fn price(value: &str) -> Option<f64> {
+    value.parse::<f64>().ok().filter(|price| price.is_finite() && *price >= 0.0)
 }
 
 #[derive(Deserialize, Debug)]
@@ -14,32 +84,220 @@ struct OpenRouterModelsResponse {
     data: Vec<OpenRouterModel>,
 }
 
-const OPENROUTER_MODELS_URL: &str = "https://openrouter.ai/api/v1/models";
+/// The catalogue as last downloaded, stored in `openrouter_models.json` next
+/// to the binary so it doesn't have to be fetched on every run and is still
+/// available offline.
+#[derive(Serialize, Deserialize, Debug)]
+struct CachedCatalogue {
+    /// Seconds since the Unix epoch.
+    fetched_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_modified: Option<String>,
+    models: Vec<OpenRouterModel>,
+}
+
+/// How long a cached catalogue is used without asking OpenRouter whether it changed.
+const CATALOGUE_TTL_SECS: u64 = 24 * 60 * 60;
+
+/// Provider routing preferences, sent as `provider` with each chat request.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ProviderPreferences {
+    /// Providers to try first, in order, e.g. `["anthropic", "openai"]`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub order: Vec<String>,
+    /// Whether other providers may serve the request when those in `order` can't.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_fallbacks: Option<bool>,
+    /// `deny`, the default, only routes to providers that don't store or train on prompts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_collection: Option<DataCollection>,
+    /// Acceptable quantizations, e.g. `["fp8", "bf16"]`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub quantizations: Vec<String>,
+}
+
+impl ProviderPreferences {
+    /// What `--config` writes for new OpenRouter models.
+    pub fn deny_data_collection() -> Self {
+        ProviderPreferences { data_collection: Some(DataCollection::Deny), ..Default::default() }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DataCollection {
+    Allow,
+    Deny,
+}
+
+/// The API key's label, spending limit and rate limit, from `/key`.
+#[derive(Deserialize, Debug)]
+pub struct KeyInfo {
+    pub label: Option<String>,
+    /// Credits used by this key.
+    pub usage: Option<f64>,
+    /// Credit limit of this key, if it has one.
+    pub limit: Option<f64>,
+    pub limit_remaining: Option<f64>,
+    #[serde(default)]
+    pub is_free_tier: bool,
+    pub rate_limit: Option<RateLimit>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RateLimit {
+    pub requests: Option<u64>,
+    pub interval: Option<String>,
+}
+
+/// Credits bought and used on the account, from `/credits`.
+#[derive(Deserialize, Debug)]
+pub struct Credits {
+    pub total_credits: f64,
+    pub total_usage: f64,
+}
+
+/// Stats for one generation, from `/generation?id=`.
+#[derive(Deserialize, Debug)]
+pub struct GenerationStats {
+    pub id: String,
+    pub model: Option<String>,
+    pub provider_name: Option<String>,
+    pub created_at: Option<String>,
+    /// Milliseconds until the response started.
+    pub latency: Option<f64>,
+    /// Milliseconds spent generating.
+    pub generation_time: Option<f64>,
+    pub tokens_prompt: Option<u64>,
+    pub tokens_completion: Option<u64>,
+    pub native_tokens_prompt: Option<u64>,
+    pub native_tokens_completion: Option<u64>,
+    pub native_tokens_reasoning: Option<u64>,
+    pub total_cost: Option<f64>,
+    pub finish_reason: Option<String>,
+    #[serde(default)]
+    pub streamed: bool,
+}
+
+/// OpenRouter wraps single objects in `data`.
+#[derive(Deserialize, Debug)]
+struct DataResponse<T> {
+    data: T,
+}
+
+const OPENROUTER_API_URL: &str = "https://openrouter.ai/api/v1";
 // Recommended headers for OpenRouter. Replace with your actual app URL if you have one.
-const APP_URL: &str = "https://github.com/dh1011/llm-term";
-const APP_TITLE: &str = "llm-term";
+pub const APP_URL: &str = "https://github.com/dh1011/llm-term";
+pub const APP_TITLE: &str = "llm-term";
 
+/// Base URL of the API.
+pub fn openrouter_base_url() -> String {
+    #[cfg(test)]
+    if let Some(base_url) = crate::test_server::base_url() {
+        return base_url;
+    }
+    OPENROUTER_API_URL.to_string()
+}
+
+/// Sends an authenticated GET to an API path, e.g. `/models`.
+fn get(path: &str, query: &[(&str, &str)], headers: &[(reqwest::header::HeaderName, &str)], api_key: &str, context: &str) -> Result<reqwest::blocking::Response, Box<dyn std::error::Error>> {
+    let client = http::client()?; // Sets the User-Agent and the configured timeouts
+    let url = format!("{}{}", openrouter_base_url().trim_end_matches('/'), path);
+
+    Ok(http::send_with_retry(context, || {
+        let mut request = client
+            .get(&url)
+            .query(query)
+            .bearer_auth(api_key)
+            .header("HTTP-Referer", APP_URL) // Recommended by OpenRouter
+            .header("X-Title", APP_TITLE);    // Recommended by OpenRouter
+        for (name, value) in headers {
+            request = request.header(name, *value);
+        }
+        request
+    })?)
+}
+
+fn get_catalogue_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let exe_path = std::env::current_exe()?;
+    let exe_dir = exe_path.parent().ok_or("Failed to get executable directory")?;
+    Ok(exe_dir.join("openrouter_models.json"))
+}
+
+fn load_catalogue() -> Option<CachedCatalogue> {
+    let content = fs::read_to_string(get_catalogue_path().ok()?).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+fn save_catalogue(catalogue: &CachedCatalogue) -> Result<(), Box<dyn std::error::Error>> {
+    fs::write(get_catalogue_path()?, serde_json::to_string(catalogue)?)?;
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0)
+}
+
+/// The model catalogue, from the local cache while it is younger than a day.
+/// After that OpenRouter is asked whether it changed (ETag and
+/// If-Modified-Since), and if it can't be reached the cached copy is used
+/// whatever its age.
 pub fn fetch_openrouter_models(api_key: &str) -> Result<Vec<OpenRouterModel>, Box<dyn std::error::Error>> {
-    let client = reqwest::blocking::Client::builder()
-        .user_agent(format!("{}/{}", APP_TITLE, env!("CARGO_PKG_VERSION"))) // Good practice to set User-Agent
-        .build()?;
-
-    let response = client
-        .get(OPENROUTER_MODELS_URL)
-        .bearer_auth(api_key)
-        .header("HTTP-Referer", APP_URL) // Recommended by OpenRouter
-        .header("X-Title", APP_TITLE)     // Recommended by OpenRouter
-        .send()?;
-
-    let status = response.status();
-    if !status.is_success() {
-        let error_body = response.text().unwrap_or_else(|_| "No error body".to_string());
-        return Err(format!(
-            "Failed to fetch models from OpenRouter: {} - {}",
-            status,
-            error_body
-        )
-        .into());
+    let cached = load_catalogue();
+    if let Some(catalogue) = cached.as_ref().filter(|catalogue| now().saturating_sub(catalogue.fetched_at) < CATALOGUE_TTL_SECS) {
+        return Ok(catalogue.models.clone());
+    }
+
+    match refresh_catalogue(api_key, cached.as_ref()) {
+        Ok(catalogue) => {
+            if let Err(e) = save_catalogue(&catalogue) {
+                eprintln!("{}", format!("Could not cache the OpenRouter model catalogue: {}", e).yellow());
+            }
+            Ok(catalogue.models)
+        }
+        Err(e) => match cached {
+            Some(catalogue) => {
+                eprintln!(
+                    "{}",
+                    format!("{}. Using the model catalogue cached on {}.", e, usage::utc_date(catalogue.fetched_at)).yellow(),
+                );
+                Ok(catalogue.models)
+            }
+            None => Err(e),
+        },
+    }
+}
+
+/// The catalogue as cached, however old, without going to the network.
+pub fn cached_openrouter_models() -> Option<Vec<OpenRouterModel>> {
+    load_catalogue().map(|catalogue| catalogue.models)
+}
+
+fn refresh_catalogue(api_key: &str, cached: Option<&CachedCatalogue>) -> Result<CachedCatalogue, Box<dyn std::error::Error>> {
+    let mut headers = Vec::new();
+    if let Some(etag) = cached.and_then(|catalogue| catalogue.etag.as_deref()) {
+        headers.push((IF_NONE_MATCH, etag));
+    }
+    if let Some(last_modified) = cached.and_then(|catalogue| catalogue.last_modified.as_deref()) {
+        headers.push((IF_MODIFIED_SINCE, last_modified));
+    }
+
+    let response = get("/models", &[], &headers, api_key, "Failed to fetch models from OpenRouter")?;
+    let header = |name| response.headers().get(name).and_then(|value| value.to_str().ok()).map(str::to_string);
+    let etag = header(ETAG);
+    let last_modified = header(LAST_MODIFIED);
+
+    if response.status() == StatusCode::NOT_MODIFIED {
+        if let Some(cached) = cached {
+            return Ok(CachedCatalogue {
+                fetched_at: now(),
+                etag: etag.or_else(|| cached.etag.clone()),
+                last_modified: last_modified.or_else(|| cached.last_modified.clone()),
+                models: cached.models.clone(),
+            });
+        }
     }
 
     let models_response: OpenRouterModelsResponse = response.json()?;
@@ -51,5 +309,96 @@ pub fn fetch_openrouter_models(api_key: &str) -> Result<Vec<OpenRouterModel>, Bo
     // Sort models by ID for consistent display
     models.sort_by(|a, b| a.id.cmp(&b.id));
 
-    Ok(models)
-}
\ No newline at end of file
+    Ok(CachedCatalogue { fetched_at: now(), etag, last_modified, models })
+}
+
+/// Pricing for one model, from the catalogue cached on disk, read once per run.
+/// It is never downloaded here, so recording usage doesn't hold up an answer;
+/// without a cached catalogue the cost is unknown.
+pub fn model_pricing(model_id: &str) -> Option<OpenRouterPricing> {
+    static CATALOGUE: OnceLock<Option<Vec<OpenRouterModel>>> = OnceLock::new();
+
+    CATALOGUE.get_or_init(cached_openrouter_models)
+        .as_ref()?
+        .iter()
+        .find(|model| model.id == model_id)
+        .and_then(|model| model.pricing.clone())
+}
+
+pub fn fetch_key_info(api_key: &str) -> Result<KeyInfo, Box<dyn std::error::Error>> {
+    let response: DataResponse<KeyInfo> = get("/key", &[], &[], api_key, "Failed to fetch OpenRouter key info")?.json()?;
+    Ok(response.data)
+}
+
+pub fn fetch_credits(api_key: &str) -> Result<Credits, Box<dyn std::error::Error>> {
+    let response: DataResponse<Credits> = get("/credits", &[], &[], api_key, "Failed to fetch OpenRouter credits")?.json()?;
+    Ok(response.data)
+}
+
+/// Looks up a generation by the id OpenRouter returned with the completion.
+/// Stats can take a moment to appear after the generation finishes.
+pub fn fetch_generation(api_key: &str, id: &str) -> Result<GenerationStats, Box<dyn std::error::Error>> {
+    let context = format!("Failed to fetch OpenRouter generation {}", id);
+    let response: DataResponse<GenerationStats> = get("/generation", &[("id", id)], &[], api_key, &context)?.json()?;
+    Ok(response.data)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::test_server;
+    use super::{fetch_credits, fetch_generation, OpenRouterPricing};
+
+    #[test]
+    fn looks_up_a_generation() {
+        let response = json!({ "data": {
+            "id": "gen-1",
+            "model": "meta-llama/llama-3.1-70b-instruct",
+            "provider_name": "Together",
+            "tokens_prompt": 12,
+            "tokens_completion": 4,
+            "total_cost": 0.00002,
+        } });
+        let (address, captured) = test_server::serve_once(200, "application/json", response.to_string());
+        test_server::redirect(format!("{}/api/v1", address));
+
+        let generation = fetch_generation("test-key", "gen-1").unwrap();
+        let captured = captured.recv().unwrap();
+
+        assert_eq!(captured.path, "/api/v1/generation?id=gen-1");
+        assert_eq!(captured.headers["authorization"], "Bearer test-key");
+        assert_eq!(captured.headers["x-title"], "llm-term");
+        assert_eq!(generation.provider_name.as_deref(), Some("Together"));
+        assert_eq!(generation.total_cost, Some(0.00002));
+    }
+
+    #[test]
+    fn reads_the_credits() {
+        let response = json!({ "data": { "total_credits": 10.0, "total_usage": 2.5 } });
+        let (address, captured) = test_server::serve_once(200, "application/json", response.to_string());
+        test_server::redirect(format!("{}/api/v1", address));
+
+        let credits = fetch_credits("test-key").unwrap();
+
+        assert_eq!(captured.recv().unwrap().path, "/api/v1/credits");
+        assert_eq!((credits.total_credits, credits.total_usage), (10.0, 2.5));
+    }
+
+    #[test]
+    fn leaves_variable_and_unparsable_prices_unknown() {
+        let pricing = |prompt: &str, completion: &str, request: &str| OpenRouterPricing {
+            prompt: prompt.to_string(),
+            completion: completion.to_string(),
+            request: request.to_string(),
+        };
+
+        assert_eq!(pricing("0.000001", "0.000002", "").per_million(), Some((1.0, 2.0)));
+        assert_eq!(pricing("0.000001", "0.000002", "").cost(1_000, 500), Some(0.002));
+        assert_eq!(pricing("0", "0", "0").cost(1_000, 500), Some(0.0));
+        assert_eq!(pricing("-1", "-1", "").per_million(), None);
+        assert_eq!(pricing("-1", "-1", "").cost(1_000, 500), None);
+        assert_eq!(pricing("0.000001", "n/a", "").cost(1_000, 500), None);
+        assert_eq!(pricing("", "0.000002", "").per_million(), None);
+        assert_eq!(pricing("0", "0", "-1").cost(1_000, 500), None);
+    }
+}
@@ -0,0 +1,253 @@
+use serde_json::{json, Map, Value};
+use std::env;
+use std::fmt;
+use std::thread;
+use crate::chat_client::{ChatClient, ChatCompletionRequest, ChatCompletionResponse, ChatMessage};
+use crate::fallback;
+use crate::sampling::SamplingSettings;
+use crate::usage::Usage;
+
+pub mod anthropic;
+mod azure;
+mod gemini;
+pub mod local;
+pub mod ollama;
+mod openai_compatible;
+pub mod openai;
+mod openrouter;
+
+pub use anthropic::AnthropicProvider;
+pub use azure::AzureOpenAiProvider;
+pub use gemini::GeminiProvider;
+pub use local::LocalProvider;
+pub use ollama::OllamaProvider;
+pub use openai::OpenAiProvider;
+pub use openai_compatible::OpenAiCompatibleProvider;
+pub use openrouter::OpenRouterProvider;
+
+/// Everything a provider needs to generate a single command.
+pub struct ChatRequest<'a> {
+    pub system_prompt: &'a str,
+    pub user_prompt: &'a str,
+    pub max_tokens: i32,
+    /// JSON schema the answer should follow, when structured output is requested.
+    pub response_schema: Option<&'a Value>,
+    /// Whether to ask for token log probabilities, so the answer's confidence can be judged.
+    pub logprobs: bool,
+    /// Sampling parameters, already resolved for this model.
+    pub sampling: SamplingSettings,
+}
+
+/// A model's answer, and how confident it was in it where the backend can tell.
+/// The text may be empty: an empty answer still used tokens.
+pub struct Completion {
+    pub text: String,
+    /// Geometric mean of the token probabilities, from 0 to 1.
+    pub confidence: Option<f64>,
+    /// Tokens the request used. When one request returns several answers, the
+    /// first one carries the usage for all of them.
+    pub usage: Option<Usage>,
+    /// The backend's id for the request, such as an OpenRouter generation id.
+    pub id: Option<String>,
+    /// The model that answered, when the backend reports it. OpenRouter may
+    /// have served one of the model's `models` fallbacks.
+    pub model: Option<String>,
+}
+
+impl Completion {
+    /// An answer from a backend that doesn't report confidence.
+    pub fn text(text: String, usage: Option<Usage>) -> Self {
+        Completion { text, confidence: None, usage, id: None, model: None }
+    }
+}
+
+/// A backend that can turn a prompt into a shell command.
+///
+/// The default methods speak the OpenAI chat completions protocol, so an
+/// OpenAI-compatible backend only has to describe its model, endpoint and key.
+/// Backends with a different wire format override `complete` and `list_models`.
+///
+/// Providers are shared between the threads that run parallel requests, so
+/// they must be `Sync`.
+pub trait Provider: Sync {
+    /// Model identifier sent to the API.
+    fn model_name(&self) -> String;
+
+    /// Base URL of the API.
+    fn endpoint(&self) -> String;
+
+    /// API key used to authenticate against the endpoint.
+    fn api_key(&self) -> Result<String, Box<dyn std::error::Error>>;
+
+    /// Client for the endpoint, authenticated with a bearer token when the backend has a key.
+    fn chat_client(&self) -> Result<ChatClient, Box<dyn std::error::Error>> {
+        let client = ChatClient::new(&self.endpoint())?;
+        let api_key = self.api_key()?;
+        Ok(if api_key.is_empty() { client } else { client.bearer_auth(&api_key) })
+    }
+
+    /// Whether the backend accepts a JSON schema in `response_format`.
+    fn supports_json_schema(&self) -> bool {
+        true
+    }
+
+    /// Whether the backend reports token usage at the end of a stream when asked
+    /// with `stream_options`.
+    fn supports_stream_usage(&self) -> bool {
+        true
+    }
+
+    /// Whether the backend returns token log probabilities when asked for `logprobs`.
+    fn supports_logprobs(&self) -> bool {
+        false
+    }
+
+    /// Whether the model reasons before answering and so rejects
+    /// `temperature`, `top_p`, the penalties, `stop` and `logprobs`, as
+    /// OpenAI's o-series do.
+    fn is_reasoning_model(&self) -> bool {
+        openai::is_reasoning_model(&self.model_name())
+    }
+
+    /// Whether the token limit has to be sent as `max_completion_tokens`,
+    /// which OpenAI's own API requires for reasoning models. Proxies such as
+    /// OpenRouter translate `max_tokens` themselves.
+    fn uses_max_completion_tokens(&self) -> bool {
+        false
+    }
+
+    /// Fields beyond the OpenAI protocol that this backend sends with every request.
+    fn extra_body(&self) -> Map<String, Value> {
+        Map::new()
+    }
+
+    fn build_request(&self, request: &ChatRequest) -> ChatCompletionRequest {
+        let response_format = request.response_schema
+            .filter(|_| self.supports_json_schema())
+            .map(|schema| json!({
+                "type": "json_schema",
+                "json_schema": { "name": "shell_command", "strict": true, "schema": schema },
+            }));
+
+        let sampling = &request.sampling;
+        let reasoning = self.is_reasoning_model();
+        let completion_limit = reasoning && self.uses_max_completion_tokens();
+
+        ChatCompletionRequest {
+            model: self.model_name(),
+            max_tokens: Some(request.max_tokens).filter(|_| !completion_limit),
+            max_completion_tokens: Some(request.max_tokens).filter(|_| completion_limit),
+            temperature: Some(sampling.temperature()).filter(|_| !reasoning),
+            top_p: sampling.top_p.filter(|_| !reasoning),
+            frequency_penalty: sampling.frequency_penalty.filter(|_| !reasoning),
+            presence_penalty: sampling.presence_penalty.filter(|_| !reasoning),
+            stop: (!sampling.stop.is_empty() && !reasoning).then(|| sampling.stop.clone()),
+            seed: sampling.seed,
+            response_format,
+            logprobs: (request.logprobs && self.supports_logprobs() && !reasoning).then_some(true),
+            extra: self.extra_body(),
+            messages: vec![
+                ChatMessage::system(request.system_prompt),
+                ChatMessage::user(request.user_prompt),
+            ],
+            ..Default::default()
+        }
+    }
+
+    /// Whether the backend honours `n` and returns several choices from one request.
+    fn supports_n(&self) -> bool {
+        false
+    }
+
+    /// Every choice in the response, in order.
+    fn parse_response(&self, response: ChatCompletionResponse) -> Vec<Completion> {
+        let mut usage = response.usage;
+        let mut id = response.id;
+        let model = response.model;
+        response.choices.into_iter()
+            .map(|choice| Completion {
+                confidence: choice.confidence(),
+                text: choice.message.and_then(|message| message.content).unwrap_or_default(),
+                usage: usage.take(),
+                id: id.take(),
+                model: model.clone(),
+            })
+            .collect()
+    }
+
+    fn complete(&self, request: &ChatRequest) -> Result<Option<Completion>, Box<dyn std::error::Error>> {
+        let response = self.chat_client()?.chat_completion(&self.build_request(request))?;
+        Ok(self.parse_response(response).into_iter().next())
+    }
+
+    /// Asks for `n` alternative answers: in one request where the backend
+    /// supports `n`, otherwise as `n` requests in parallel. Fails only if
+    /// every parallel request fails, with the first request's error.
+    fn complete_many(&self, request: &ChatRequest, n: u32) -> Result<Vec<Completion>, Box<dyn std::error::Error>> {
+        if n <= 1 {
+            return Ok(self.complete(request)?.into_iter().collect());
+        }
+
+        if self.supports_n() {
+            let body = ChatCompletionRequest { n: Some(n), ..self.build_request(request) };
+            let response = self.chat_client()?.chat_completion(&body)?;
+            return Ok(self.parse_response(response));
+        }
+
+        let results: Vec<Result<Option<Completion>, fallback::SendableError>> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..n)
+                .map(|_| scope.spawn(|| self.complete(request).map_err(fallback::sendable)))
+                .collect();
+            handles.into_iter()
+                .map(|handle| handle.join().unwrap_or_else(|_| Err("request thread panicked".into())))
+                .collect()
+        });
+
+        let mut answers = Vec::new();
+        let mut first_error = None;
+        for result in results {
+            match result {
+                Ok(answer) => answers.extend(answer),
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+
+        match first_error {
+            Some(e) if answers.is_empty() => Err(e),
+            _ => Ok(answers),
+        }
+    }
+
+    /// Like `complete`, but passes text to `on_token` as the server streams it.
+    fn complete_streaming(&self, request: &ChatRequest, on_token: &mut dyn FnMut(&str)) -> Result<Option<Completion>, Box<dyn std::error::Error>> {
+        let body = ChatCompletionRequest {
+            stream_options: self.supports_stream_usage().then(|| json!({ "include_usage": true })),
+            ..self.build_request(request)
+        };
+        let streamed = self.chat_client()?.chat_completion_stream(&body, on_token)?;
+        Ok(Some(Completion { text: streamed.text, confidence: streamed.confidence, usage: streamed.usage, id: streamed.id, model: streamed.model }))
+    }
+
+    fn list_models(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        self.chat_client()?.list_models()
+    }
+}
+
+/// Returned when the environment variable holding a backend's API key is not set.
+#[derive(Debug)]
+pub struct MissingCredential(pub String);
+
+impl fmt::Display for MissingCredential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} environment variable not set", self.0)
+    }
+}
+
+impl std::error::Error for MissingCredential {}
+
+/// Reads an API key from the environment with a readable error if it is missing.
+fn require_env(var: &str) -> Result<String, Box<dyn std::error::Error>> {
+    env::var(var).map_err(|_| MissingCredential(var.to_string()).into())
+}
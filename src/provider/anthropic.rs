@@ -0,0 +1,347 @@
+use serde::{Deserialize, Serialize};
+use crate::{http, sse};
+use crate::usage::Usage;
+use super::{require_env, ChatRequest, Completion, Provider};
+
+const ANTHROPIC_API_URL: &str = "https://api.anthropic.com/v1";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Most tokens each model can answer with, by id prefix. The context window
+/// is far larger, but `max_tokens` above this is rejected.
+const MAX_OUTPUT_TOKENS: &[(&str, i32)] = &[
+    ("claude-3-haiku", 4_096),
+    ("claude-3-opus", 4_096),
+    ("claude-3-sonnet", 4_096),
+    ("claude-3-5-haiku", 8_192),
+    ("claude-3-5-sonnet", 8_192),
+    ("claude-3-7-sonnet", 64_000),
+    ("claude-haiku-4-5", 64_000),
+    ("claude-opus-4", 32_000),
+    ("claude-opus-4-5", 64_000),
+    ("claude-sonnet-4", 64_000),
+];
+
+/// Output limit of a model, from the entry with the longest matching prefix,
+/// so dated snapshots and `-latest` aliases are covered.
+pub fn max_output_tokens(model_id: &str) -> Option<i32> {
+    MAX_OUTPUT_TOKENS.iter()
+        .filter(|(prefix, _)| model_id.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, max_tokens)| *max_tokens)
+}
+
+/// Anthropic's native Messages API.
+pub struct AnthropicProvider {
+    pub model_name: String,
+}
+
+#[derive(Serialize)]
+struct MessagesRequest<'a> {
+    model: String,
+    max_tokens: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    stop_sequences: &'a [String],
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    system: &'a str,
+    messages: Vec<MessageParam<'a>>,
+}
+
+#[derive(Serialize)]
+struct MessageParam<'a> {
+    role: &'static str,
+    content: Vec<ContentBlock<'a>>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentBlock<'a> {
+    Text { text: &'a str },
+}
+
+#[derive(Deserialize)]
+struct MessagesResponse {
+    content: Vec<ResponseBlock>,
+    usage: Option<MessagesUsage>,
+}
+
+#[derive(Deserialize, Default)]
+struct MessagesUsage {
+    #[serde(default)]
+    input_tokens: u64,
+    #[serde(default)]
+    output_tokens: u64,
+}
+
+#[derive(Deserialize)]
+struct ResponseBlock {
+    #[serde(rename = "type")]
+    kind: String,
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StreamEvent {
+    #[serde(rename = "type")]
+    kind: String,
+    delta: Option<StreamDelta>,
+    /// Input tokens arrive with `message_start`.
+    message: Option<StreamMessage>,
+    /// Output tokens arrive with `message_delta`.
+    usage: Option<MessagesUsage>,
+}
+
+#[derive(Deserialize)]
+struct StreamMessage {
+    usage: Option<MessagesUsage>,
+}
+
+#[derive(Deserialize)]
+struct StreamDelta {
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ModelsResponse {
+    data: Vec<ModelEntry>,
+}
+
+#[derive(Deserialize)]
+struct ModelEntry {
+    id: String,
+}
+
+impl AnthropicProvider {
+    fn send_messages(&self, request: &ChatRequest, stream: bool) -> Result<reqwest::blocking::Response, Box<dyn std::error::Error>> {
+        let body = MessagesRequest {
+            model: self.model_name(),
+            max_tokens: request.max_tokens,
+            // The Messages API has no seed or penalties, and current models
+            // reject temperature and top_p together; top_p wins when set.
+            temperature: request.sampling.top_p.is_none().then(|| request.sampling.temperature()),
+            top_p: request.sampling.top_p,
+            stop_sequences: &request.sampling.stop,
+            stream,
+            system: request.system_prompt,
+            messages: vec![MessageParam {
+                role: "user",
+                content: vec![ContentBlock::Text { text: request.user_prompt }],
+            }],
+        };
+
+        let client = http::client()?;
+        let url = format!("{}/messages", self.endpoint().trim_end_matches('/'));
+        let api_key = self.api_key()?;
+
+        Ok(http::send_with_retry(&format!("API Error for model {}", self.model_name), || {
+            client.post(&url)
+                .header("x-api-key", &api_key)
+                .header("anthropic-version", ANTHROPIC_VERSION)
+                .json(&body)
+        })?)
+    }
+}
+
+impl Provider for AnthropicProvider {
+    fn model_name(&self) -> String {
+        self.model_name.clone()
+    }
+
+    fn endpoint(&self) -> String {
+        #[cfg(test)]
+        if let Some(base_url) = crate::test_server::base_url() {
+            return base_url;
+        }
+        ANTHROPIC_API_URL.to_string()
+    }
+
+    fn api_key(&self) -> Result<String, Box<dyn std::error::Error>> {
+        require_env("ANTHROPIC_API_KEY")
+    }
+
+    fn complete(&self, request: &ChatRequest) -> Result<Option<Completion>, Box<dyn std::error::Error>> {
+        let response: MessagesResponse = self.send_messages(request, false)?.json()?;
+        let usage = response.usage.map(|usage| Usage::new(usage.input_tokens, usage.output_tokens));
+        let text: String = response.content.into_iter()
+            .filter(|block| block.kind == "text")
+            .filter_map(|block| block.text)
+            .collect();
+
+        Ok(Some(Completion::text(text, usage)))
+    }
+
+    fn complete_streaming(&self, request: &ChatRequest, on_token: &mut dyn FnMut(&str)) -> Result<Option<Completion>, Box<dyn std::error::Error>> {
+        let response = self.send_messages(request, true)?;
+
+        let mut text = String::new();
+        let mut counts = MessagesUsage::default();
+        sse::read_events(response, |data| {
+            let event: StreamEvent = serde_json::from_str(data)?;
+            if let Some(usage) = event.message.and_then(|message| message.usage) {
+                counts.input_tokens = usage.input_tokens;
+            }
+            if let Some(usage) = event.usage {
+                counts.output_tokens = usage.output_tokens;
+            }
+            match event.kind.as_str() {
+                "content_block_delta" => {
+                    if let Some(delta_text) = event.delta.and_then(|delta| delta.text) {
+                        on_token(&delta_text);
+                        text.push_str(&delta_text);
+                    }
+                    Ok(true)
+                }
+                "message_stop" => Ok(false),
+                "error" => Err(format!("API Error for model {}: {}", self.model_name, data).into()),
+                _ => Ok(true),
+            }
+        })?;
+
+        Ok(Some(Completion::text(text, Some(Usage::new(counts.input_tokens, counts.output_tokens)))))
+    }
+
+    fn list_models(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let client = http::client()?;
+        let url = format!("{}/models", self.endpoint().trim_end_matches('/'));
+        let api_key = self.api_key()?;
+
+        let response = http::send_with_retry("Failed to list Anthropic models", || {
+            client.get(&url)
+                .header("x-api-key", &api_key)
+                .header("anthropic-version", ANTHROPIC_VERSION)
+        })?;
+
+        let models: ModelsResponse = response.json()?;
+        Ok(models.data.into_iter().map(|model| model.id).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::http::{HttpError, HttpErrorKind};
+    use crate::provider::Provider;
+    use crate::sampling::SamplingSettings;
+    use crate::test_server::{self, ENVIRONMENT};
+    use super::{max_output_tokens, AnthropicProvider};
+
+    fn provider() -> AnthropicProvider {
+        AnthropicProvider { model_name: "claude-test".to_string() }
+    }
+
+    fn text_response() -> String {
+        json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "content": [
+                { "type": "thinking", "thinking": "the user wants a listing" },
+                { "type": "text", "text": "ls " },
+                { "type": "text", "text": "-la" },
+            ],
+            "usage": { "input_tokens": 12, "output_tokens": 4 },
+        }).to_string()
+    }
+
+    #[test]
+    fn sends_a_messages_request() {
+        let _environment = ENVIRONMENT.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let captured = test_server::serve_backend("/v1", "ANTHROPIC_API_KEY", 200, "application/json", text_response());
+
+        provider().complete(&test_server::chat_request(SamplingSettings::default())).unwrap();
+        let captured = captured.recv().unwrap();
+
+        assert_eq!(captured.path, "/v1/messages");
+        assert_eq!(captured.headers["x-api-key"], "test-key");
+        assert_eq!(captured.headers["anthropic-version"], "2023-06-01");
+        assert!(!captured.headers.contains_key("authorization"));
+        assert_eq!(captured.body, json!({
+            "model": "claude-test",
+            "max_tokens": 64,
+            "temperature": 0.5,
+            "system": "answer with a command",
+            "messages": [{ "role": "user", "content": [{ "type": "text", "text": "list files" }] }],
+        }));
+    }
+
+    #[test]
+    fn sends_top_p_without_temperature() {
+        let _environment = ENVIRONMENT.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let captured = test_server::serve_backend("/v1", "ANTHROPIC_API_KEY", 200, "application/json", text_response());
+
+        let sampling = SamplingSettings { top_p: Some(0.9), stop: vec!["\n\n".to_string()], seed: Some(7), ..Default::default() };
+        provider().complete(&test_server::chat_request(sampling)).unwrap();
+        let body = captured.recv().unwrap().body;
+
+        assert_eq!(body.get("temperature"), None);
+        assert_eq!(body["top_p"], json!(0.9));
+        assert_eq!(body["stop_sequences"], json!(["\n\n"]));
+        assert_eq!(body.get("seed"), None);
+    }
+
+    #[test]
+    fn joins_the_text_blocks_of_the_answer() {
+        let _environment = ENVIRONMENT.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let _captured = test_server::serve_backend("/v1", "ANTHROPIC_API_KEY", 200, "application/json", text_response());
+
+        let completion = provider().complete(&test_server::chat_request(SamplingSettings::default())).unwrap().unwrap();
+
+        assert_eq!(completion.text, "ls -la");
+        let usage = completion.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (12, 4));
+    }
+
+    #[test]
+    fn reads_a_streamed_answer() {
+        let _environment = ENVIRONMENT.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let events = [
+            ("message_start", json!({ "type": "message_start", "message": { "usage": { "input_tokens": 12, "output_tokens": 1 } } })),
+            ("content_block_start", json!({ "type": "content_block_start", "index": 0, "content_block": { "type": "text", "text": "" } })),
+            ("ping", json!({ "type": "ping" })),
+            ("content_block_delta", json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "text_delta", "text": "ls " } })),
+            ("content_block_delta", json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "text_delta", "text": "-la" } })),
+            ("content_block_stop", json!({ "type": "content_block_stop", "index": 0 })),
+            ("message_delta", json!({ "type": "message_delta", "delta": { "stop_reason": "end_turn" }, "usage": { "output_tokens": 4 } })),
+            ("message_stop", json!({ "type": "message_stop" })),
+        ];
+        let stream: String = events.iter().map(|(name, data)| format!("event: {}\ndata: {}\n\n", name, data)).collect();
+        let captured = test_server::serve_backend("/v1", "ANTHROPIC_API_KEY", 200, "text/event-stream", stream);
+
+        let mut tokens = Vec::new();
+        let completion = provider()
+            .complete_streaming(&test_server::chat_request(SamplingSettings::default()), &mut |token| tokens.push(token.to_string()))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(captured.recv().unwrap().body["stream"], json!(true));
+        assert_eq!(tokens, ["ls ", "-la"]);
+        assert_eq!(completion.text, "ls -la");
+        let usage = completion.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (12, 4));
+    }
+
+    #[test]
+    fn reports_api_errors_with_their_status() {
+        let _environment = ENVIRONMENT.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let error = json!({ "type": "error", "error": { "type": "invalid_request_error", "message": "max_tokens: too large" } });
+        let _captured = test_server::serve_backend("/v1", "ANTHROPIC_API_KEY", 400, "application/json", error.to_string());
+
+        let error = provider().complete(&test_server::chat_request(SamplingSettings::default())).err().unwrap();
+        let error = error.downcast_ref::<HttpError>().unwrap();
+
+        assert_eq!(error.kind, HttpErrorKind::Status(reqwest::StatusCode::BAD_REQUEST));
+        assert!(error.detail.contains("max_tokens: too large"));
+    }
+
+    #[test]
+    fn looks_up_output_limits_by_prefix() {
+        assert_eq!(max_output_tokens("claude-3-5-haiku-latest"), Some(8_192));
+        assert_eq!(max_output_tokens("claude-opus-4-1-20250805"), Some(32_000));
+        assert_eq!(max_output_tokens("claude-opus-4-5-20251101"), Some(64_000));
+        assert_eq!(max_output_tokens("claude-2.1"), None);
+    }
+}
@@ -0,0 +1,55 @@
+use crate::chat_client::ChatClient;
+use super::{openai, require_env, Provider};
+
+/// An Azure OpenAI resource, addressed by deployment rather than model name.
+pub struct AzureOpenAiProvider {
+    pub endpoint: String,
+    pub deployment: String,
+    pub api_version: String,
+    /// The OpenAI model the deployment runs, e.g. `o3-mini`. Deployment names
+    /// are chosen freely, so this is the only way to tell a reasoning model.
+    pub model: Option<String>,
+}
+
+impl Provider for AzureOpenAiProvider {
+    fn model_name(&self) -> String {
+        self.deployment.clone()
+    }
+
+    fn endpoint(&self) -> String {
+        format!("{}/openai/deployments/{}", self.endpoint.trim_end_matches('/'), self.deployment)
+    }
+
+    fn supports_n(&self) -> bool {
+        true
+    }
+
+    fn supports_logprobs(&self) -> bool {
+        true
+    }
+
+    fn is_reasoning_model(&self) -> bool {
+        self.model.as_deref().is_some_and(openai::is_reasoning_model)
+    }
+
+    fn uses_max_completion_tokens(&self) -> bool {
+        true
+    }
+
+    fn api_key(&self) -> Result<String, Box<dyn std::error::Error>> {
+        require_env("AZURE_OPENAI_API_KEY")
+    }
+
+    // Azure speaks the OpenAI wire format but authenticates with an `api-key`
+    // header and versions every call.
+    fn chat_client(&self) -> Result<ChatClient, Box<dyn std::error::Error>> {
+        Ok(ChatClient::new(&self.endpoint())?
+            .header("api-key", &self.api_key()?)
+            .query("api-version", &self.api_version))
+    }
+
+    // The data-plane API has no deployment listing; the configured one is all we can offer.
+    fn list_models(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        Ok(vec![self.deployment.clone()])
+    }
+}
@@ -0,0 +1,348 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::{http, sse};
+use crate::usage::Usage;
+use super::{require_env, ChatRequest, Completion, Provider};
+
+const GEMINI_API_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+
+/// Google's Gemini `generateContent` REST API.
+pub struct GeminiProvider {
+    pub model_name: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerateContentRequest<'a> {
+    system_instruction: Content<'a>,
+    contents: Vec<Content<'a>>,
+    generation_config: GenerationConfig<'a>,
+}
+
+#[derive(Serialize)]
+struct Content<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<&'static str>,
+    parts: Vec<Part<'a>>,
+}
+
+#[derive(Serialize)]
+struct Part<'a> {
+    text: &'a str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerationConfig<'a> {
+    max_output_tokens: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    stop_sequences: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_mime_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_schema: Option<Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerateContentResponse {
+    #[serde(default)]
+    candidates: Vec<Candidate>,
+    prompt_feedback: Option<PromptFeedback>,
+    usage_metadata: Option<UsageMetadata>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UsageMetadata {
+    #[serde(default)]
+    prompt_token_count: u64,
+    #[serde(default)]
+    candidates_token_count: u64,
+}
+
+impl UsageMetadata {
+    fn usage(&self) -> Usage {
+        Usage::new(self.prompt_token_count, self.candidates_token_count)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Candidate {
+    content: Option<CandidateContent>,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CandidateContent {
+    #[serde(default)]
+    parts: Vec<CandidatePart>,
+}
+
+#[derive(Deserialize)]
+struct CandidatePart {
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PromptFeedback {
+    block_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ModelsResponse {
+    #[serde(default)]
+    models: Vec<ModelEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ModelEntry {
+    name: String,
+    #[serde(default)]
+    supported_generation_methods: Vec<String>,
+    input_token_limit: Option<i32>,
+    output_token_limit: Option<i32>,
+}
+
+/// A model that can answer `generateContent` requests, from `/models`.
+pub struct GeminiModel {
+    /// The name without its `models/` prefix.
+    pub name: String,
+    /// Tokens the model accepts as input.
+    pub input_token_limit: Option<i32>,
+    /// Tokens the model can answer with.
+    pub output_token_limit: Option<i32>,
+}
+
+/// Gemini accepts an OpenAPI-style subset of JSON schema without `additionalProperties`.
+fn gemini_schema(schema: &Value) -> Value {
+    let mut schema = schema.clone();
+    if let Value::Object(object) = &mut schema {
+        object.remove("additionalProperties");
+    }
+    schema
+}
+
+impl GenerateContentResponse {
+    /// Whether the prompt was blocked, or the answer was stopped for safety reasons.
+    fn is_blocked(&self) -> bool {
+        // A blocked prompt comes back with feedback and no candidates.
+        let prompt_blocked = self.prompt_feedback.as_ref().is_some_and(|feedback| feedback.block_reason.is_some());
+        let answer_blocked = self.candidates.first().is_some_and(|candidate| {
+            matches!(candidate.finish_reason.as_deref(), Some("SAFETY" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII"))
+        });
+        prompt_blocked || answer_blocked
+    }
+
+    /// Text of the first candidate; empty for a streamed chunk without
+    /// candidates, such as one that only carries usage.
+    fn into_text(self) -> String {
+        self.candidates.into_iter().next()
+            .and_then(|candidate| candidate.content)
+            .map(|content| content.parts.into_iter().filter_map(|part| part.text).collect())
+            .unwrap_or_default()
+    }
+}
+
+impl GeminiProvider {
+    fn send_generate(&self, request: &ChatRequest, stream: bool) -> Result<reqwest::blocking::Response, Box<dyn std::error::Error>> {
+        let body = GenerateContentRequest {
+            system_instruction: Content { role: None, parts: vec![Part { text: request.system_prompt }] },
+            contents: vec![Content { role: Some("user"), parts: vec![Part { text: request.user_prompt }] }],
+            generation_config: GenerationConfig {
+                max_output_tokens: request.max_tokens,
+                temperature: Some(request.sampling.temperature()),
+                top_p: request.sampling.top_p,
+                stop_sequences: &request.sampling.stop,
+                frequency_penalty: request.sampling.frequency_penalty,
+                presence_penalty: request.sampling.presence_penalty,
+                seed: request.sampling.seed,
+                response_mime_type: request.response_schema.map(|_| "application/json"),
+                response_schema: request.response_schema.map(gemini_schema),
+            },
+        };
+
+        let method = if stream { "streamGenerateContent" } else { "generateContent" };
+        let url = format!("{}/models/{}:{}", self.endpoint().trim_end_matches('/'), self.model_name, method);
+        let client = http::client()?;
+        let api_key = self.api_key()?;
+
+        Ok(http::send_with_retry(&format!("API Error for model {}", self.model_name), || {
+            let request = client.post(&url)
+                .header("x-goog-api-key", &api_key)
+                .json(&body);
+            if stream { request.query(&[("alt", "sse")]) } else { request }
+        })?)
+    }
+
+    /// The models that can generate content, with their token limits.
+    pub fn list_generation_models(&self) -> Result<Vec<GeminiModel>, Box<dyn std::error::Error>> {
+        let client = http::client()?;
+        let url = format!("{}/models", self.endpoint().trim_end_matches('/'));
+        let api_key = self.api_key()?;
+
+        let response = http::send_with_retry("Failed to list Gemini models", || {
+            client.get(&url).header("x-goog-api-key", &api_key)
+        })?;
+
+        let models: ModelsResponse = response.json()?;
+        Ok(models.models.into_iter()
+            .filter(|model| model.supported_generation_methods.iter().any(|method| method == "generateContent"))
+            .map(|model| GeminiModel {
+                name: model.name.trim_start_matches("models/").to_string(),
+                input_token_limit: model.input_token_limit,
+                output_token_limit: model.output_token_limit,
+            })
+            .collect())
+    }
+}
+
+impl Provider for GeminiProvider {
+    fn model_name(&self) -> String {
+        self.model_name.clone()
+    }
+
+    fn endpoint(&self) -> String {
+        #[cfg(test)]
+        if let Some(base_url) = crate::test_server::base_url() {
+            return base_url;
+        }
+        GEMINI_API_URL.to_string()
+    }
+
+    fn api_key(&self) -> Result<String, Box<dyn std::error::Error>> {
+        require_env("GEMINI_API_KEY")
+    }
+
+    fn complete(&self, request: &ChatRequest) -> Result<Option<Completion>, Box<dyn std::error::Error>> {
+        let response: GenerateContentResponse = self.send_generate(request, false)?.json()?;
+        let usage = response.usage_metadata.as_ref().map(UsageMetadata::usage);
+        // A blocked answer still used tokens.
+        let text = if response.is_blocked() { String::new() } else { response.into_text() };
+        Ok(Some(Completion::text(text, usage)))
+    }
+
+    fn complete_streaming(&self, request: &ChatRequest, on_token: &mut dyn FnMut(&str)) -> Result<Option<Completion>, Box<dyn std::error::Error>> {
+        let response = self.send_generate(request, true)?;
+
+        let mut text = String::new();
+        let mut blocked = false;
+        let mut usage = None;
+        sse::read_events(response, |data| {
+            let chunk: GenerateContentResponse = serde_json::from_str(data)?;
+            // Each chunk carries the running totals so far.
+            if let Some(metadata) = &chunk.usage_metadata {
+                usage = Some(metadata.usage());
+            }
+            if chunk.is_blocked() {
+                blocked = true;
+                return Ok(false);
+            }
+            let piece = chunk.into_text();
+            if !piece.is_empty() {
+                on_token(&piece);
+                text.push_str(&piece);
+            }
+            Ok(true)
+        })?;
+
+        if blocked {
+            text.clear();
+        }
+        Ok(Some(Completion::text(text, usage)))
+    }
+
+    fn list_models(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        Ok(self.list_generation_models()?.into_iter().map(|model| model.name).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::provider::Provider;
+    use crate::sampling::SamplingSettings;
+    use crate::test_server::{self, ENVIRONMENT};
+    use super::GeminiProvider;
+
+    fn provider() -> GeminiProvider {
+        GeminiProvider { model_name: "gemini-test".to_string() }
+    }
+
+    #[test]
+    fn sends_a_generate_content_request() {
+        let _environment = ENVIRONMENT.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let response = json!({
+            "candidates": [{ "content": { "parts": [{ "text": "ls " }, { "text": "-la" }] }, "finishReason": "STOP" }],
+            "usageMetadata": { "promptTokenCount": 12, "candidatesTokenCount": 4 },
+        });
+        let captured = test_server::serve_backend("/v1beta", "GEMINI_API_KEY", 200, "application/json", response.to_string());
+
+        let sampling = SamplingSettings { stop: vec!["\n\n".to_string()], seed: Some(7), ..Default::default() };
+        let completion = provider().complete(&test_server::chat_request(sampling)).unwrap().unwrap();
+        let captured = captured.recv().unwrap();
+
+        assert_eq!(captured.path, "/v1beta/models/gemini-test:generateContent");
+        assert_eq!(captured.headers["x-goog-api-key"], "test-key");
+        assert_eq!(captured.body, json!({
+            "systemInstruction": { "parts": [{ "text": "answer with a command" }] },
+            "contents": [{ "role": "user", "parts": [{ "text": "list files" }] }],
+            "generationConfig": { "maxOutputTokens": 64, "temperature": 0.5, "stopSequences": ["\n\n"], "seed": 7 },
+        }));
+        assert_eq!(completion.text, "ls -la");
+        let usage = completion.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (12, 4));
+    }
+
+    #[test]
+    fn reads_a_streamed_answer_with_a_usage_only_chunk() {
+        let _environment = ENVIRONMENT.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let chunks = [
+            json!({ "candidates": [{ "content": { "parts": [{ "text": "ls " }] } }] }),
+            json!({ "candidates": [{ "content": { "parts": [{ "text": "-la" }] }, "finishReason": "STOP" }] }),
+            json!({ "usageMetadata": { "promptTokenCount": 12, "candidatesTokenCount": 4 } }),
+        ];
+        let stream: String = chunks.iter().map(|chunk| format!("data: {}\r\n\r\n", chunk)).collect();
+        let captured = test_server::serve_backend("/v1beta", "GEMINI_API_KEY", 200, "text/event-stream", stream);
+
+        let mut tokens = Vec::new();
+        let completion = provider()
+            .complete_streaming(&test_server::chat_request(SamplingSettings::default()), &mut |token| tokens.push(token.to_string()))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(captured.recv().unwrap().path, "/v1beta/models/gemini-test:streamGenerateContent?alt=sse");
+        assert_eq!(tokens, ["ls ", "-la"]);
+        assert_eq!(completion.text, "ls -la");
+        assert_eq!(completion.usage.unwrap().completion_tokens, 4);
+    }
+
+    #[test]
+    fn discards_a_blocked_answer() {
+        let _environment = ENVIRONMENT.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let chunks = [
+            json!({ "candidates": [{ "content": { "parts": [{ "text": "rm " }] } }] }),
+            json!({ "candidates": [{ "finishReason": "SAFETY" }], "usageMetadata": { "promptTokenCount": 12, "candidatesTokenCount": 1 } }),
+        ];
+        let stream: String = chunks.iter().map(|chunk| format!("data: {}\r\n\r\n", chunk)).collect();
+        let _captured = test_server::serve_backend("/v1beta", "GEMINI_API_KEY", 200, "text/event-stream", stream);
+
+        let completion = provider().complete_streaming(&test_server::chat_request(SamplingSettings::default()), &mut |_| {}).unwrap().unwrap();
+
+        assert_eq!(completion.text, "");
+        assert_eq!(completion.usage.unwrap().prompt_tokens, 12);
+    }
+}
@@ -0,0 +1,245 @@
+use std::path::{Path, PathBuf};
+use super::{ChatRequest, Completion, Provider};
+
+/// A quantized GGUF model run in-process on the CPU, for machines that can't
+/// reach any API or run a daemon. Needs the `local` cargo feature.
+pub struct LocalProvider {
+    pub gguf_path: PathBuf,
+    /// `tokenizer.json` for the model; defaults to the one next to the GGUF file.
+    pub tokenizer_path: Option<PathBuf>,
+}
+
+impl LocalProvider {
+    fn tokenizer_path(&self) -> PathBuf {
+        self.tokenizer_path.clone()
+            .unwrap_or_else(|| self.gguf_path.with_file_name("tokenizer.json"))
+    }
+}
+
+impl Provider for LocalProvider {
+    fn model_name(&self) -> String {
+        self.gguf_path.file_stem().map_or_else(
+            || self.gguf_path.display().to_string(),
+            |stem| stem.to_string_lossy().into_owned(),
+        )
+    }
+
+    fn endpoint(&self) -> String {
+        self.gguf_path.display().to_string()
+    }
+
+    // Nothing leaves the machine.
+    fn api_key(&self) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(String::new())
+    }
+
+    fn complete(&self, request: &ChatRequest) -> Result<Option<Completion>, Box<dyn std::error::Error>> {
+        self.complete_streaming(request, &mut |_| {})
+    }
+
+    fn complete_streaming(&self, request: &ChatRequest, on_token: &mut dyn FnMut(&str)) -> Result<Option<Completion>, Box<dyn std::error::Error>> {
+        inference::generate(&self.gguf_path, &self.tokenizer_path(), request, on_token).map(Some)
+    }
+
+    fn list_models(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        Ok(Vec::new())
+    }
+}
+
+/// Whether this build can run GGUF models.
+pub const AVAILABLE: bool = cfg!(feature = "local");
+
+/// The architectures `inference` knows how to load.
+pub const SUPPORTED_ARCHITECTURES: &[&str] = &["llama", "qwen2"];
+
+#[cfg(not(feature = "local"))]
+mod inference {
+    use std::path::Path;
+    use crate::provider::{ChatRequest, Completion};
+
+    pub fn generate(_: &Path, _: &Path, _: &ChatRequest, _: &mut dyn FnMut(&str)) -> Result<Completion, Box<dyn std::error::Error>> {
+        Err("this build of llm-term can't run local GGUF models; rebuild it with `cargo build --release --features local`".into())
+    }
+}
+
+#[cfg(feature = "local")]
+mod inference {
+    use candle_core::quantized::gguf_file;
+    use candle_core::{Device, Tensor};
+    use candle_transformers::generation::LogitsProcessor;
+    use candle_transformers::models::{quantized_llama, quantized_qwen2};
+    use std::fs::File;
+    use std::path::{Path, PathBuf};
+    use std::sync::Mutex;
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use tokenizers::Tokenizer;
+    use crate::interrupt::{self, Cancelled};
+    use crate::provider::{ChatRequest, Completion};
+    use crate::usage::Usage;
+    use super::SUPPORTED_ARCHITECTURES;
+
+    enum Weights {
+        Llama(quantized_llama::ModelWeights),
+        Qwen2(quantized_qwen2::ModelWeights),
+    }
+
+    impl Weights {
+        fn forward(&mut self, input: &Tensor, position: usize) -> candle_core::Result<Tensor> {
+            match self {
+                Weights::Llama(model) => model.forward(input, position),
+                Weights::Qwen2(model) => model.forward(input, position),
+            }
+        }
+    }
+
+    /// How the chat is laid out for the model, recognised by the special
+    /// tokens in its vocabulary.
+    #[derive(Clone, Copy)]
+    enum Template {
+        Llama3,
+        ChatMl,
+        Instruct,
+    }
+
+    impl Template {
+        fn detect(tokenizer: &Tokenizer) -> Self {
+            if tokenizer.token_to_id("<|start_header_id|>").is_some() {
+                Template::Llama3
+            } else if tokenizer.token_to_id("<|im_start|>").is_some() {
+                Template::ChatMl
+            } else {
+                Template::Instruct
+            }
+        }
+
+        fn render(self, system: &str, user: &str) -> String {
+            match self {
+                Template::Llama3 => format!(
+                    "<|begin_of_text|><|start_header_id|>system<|end_header_id|>\n\n{}<|eot_id|><|start_header_id|>user<|end_header_id|>\n\n{}<|eot_id|><|start_header_id|>assistant<|end_header_id|>\n\n",
+                    system, user,
+                ),
+                Template::ChatMl => format!(
+                    "<|im_start|>system\n{}<|im_end|>\n<|im_start|>user\n{}<|im_end|>\n<|im_start|>assistant\n",
+                    system, user,
+                ),
+                Template::Instruct => format!("<s>[INST] {}\n\n{} [/INST]", system, user),
+            }
+        }
+    }
+
+    /// Tokens that end the answer, whichever of them the vocabulary has.
+    const STOP_TOKENS: &[&str] = &["<|eot_id|>", "<|end_of_text|>", "<|im_end|>", "<|endoftext|>", "</s>"];
+
+    struct Loaded {
+        gguf_path: PathBuf,
+        weights: Weights,
+        tokenizer: Tokenizer,
+    }
+
+    /// The model stays loaded for the rest of the run. Parallel requests take
+    /// turns, which costs nothing on a CPU that one generation already keeps busy.
+    static LOADED: Mutex<Option<Loaded>> = Mutex::new(None);
+
+    fn load(gguf_path: &Path, tokenizer_path: &Path) -> Result<Loaded, Box<dyn std::error::Error>> {
+        let mut file = File::open(gguf_path)
+            .map_err(|e| format!("Failed to open {}: {}", gguf_path.display(), e))?;
+        let content = gguf_file::Content::read(&mut file)
+            .map_err(|e| format!("{} is not a GGUF file: {}", gguf_path.display(), e))?;
+        let architecture = content.metadata.get("general.architecture")
+            .and_then(|value| value.to_string().ok())
+            .cloned()
+            .unwrap_or_default();
+
+        let weights = match architecture.as_str() {
+            "llama" => Weights::Llama(quantized_llama::ModelWeights::from_gguf(content, &mut file, &Device::Cpu)?),
+            "qwen2" => Weights::Qwen2(quantized_qwen2::ModelWeights::from_gguf(content, &mut file, &Device::Cpu)?),
+            other => {
+                return Err(format!(
+                    "{} has the unsupported architecture '{}' (supported: {})",
+                    gguf_path.display(), other, SUPPORTED_ARCHITECTURES.join(", "),
+                ).into());
+            }
+        };
+        let tokenizer = Tokenizer::from_file(tokenizer_path)
+            .map_err(|e| format!("Failed to load the tokenizer {}: {}", tokenizer_path.display(), e))?;
+
+        Ok(Loaded { gguf_path: gguf_path.to_path_buf(), weights, tokenizer })
+    }
+
+    pub fn generate(gguf_path: &Path, tokenizer_path: &Path, request: &ChatRequest, on_token: &mut dyn FnMut(&str)) -> Result<Completion, Box<dyn std::error::Error>> {
+        let mut loaded = LOADED.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if loaded.as_ref().is_none_or(|loaded| loaded.gguf_path != gguf_path) {
+            *loaded = Some(load(gguf_path, tokenizer_path)?);
+        }
+        let Some(Loaded { weights, tokenizer, .. }) = loaded.as_mut() else {
+            unreachable!("the model was just loaded");
+        };
+
+        let prompt = Template::detect(tokenizer).render(request.system_prompt, request.user_prompt);
+        let prompt_tokens = tokenizer.encode(prompt, false).map_err(|e| e.to_string())?.get_ids().to_vec();
+        let stop_tokens: Vec<u32> = STOP_TOKENS.iter().filter_map(|token| tokenizer.token_to_id(token)).collect();
+
+        // There are no penalties here; a temperature of 0 picks the likeliest token every time.
+        let sampling = &request.sampling;
+        let seed = sampling.seed.map(|seed| seed as u64).unwrap_or_else(|| {
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_nanos() as u64).unwrap_or(0)
+        });
+        let temperature = Some(f64::from(sampling.temperature())).filter(|temperature| *temperature > 0.0);
+        let mut sampler = LogitsProcessor::new(seed, temperature, sampling.top_p.map(f64::from));
+
+        let mut generated: Vec<u32> = Vec::new();
+        let mut text = String::new();
+        let mut input = prompt_tokens.clone();
+        // Position of `input` in the sequence; the model keeps earlier positions in its cache.
+        let mut position = 0;
+        while generated.len() < request.max_tokens.max(1) as usize {
+            if interrupt::cancelled() {
+                return Err(Box::new(Cancelled));
+            }
+
+            let logits = weights.forward(&Tensor::new(input.as_slice(), &Device::Cpu)?.unsqueeze(0)?, position)?.squeeze(0)?;
+            let next = sampler.sample(&logits)?;
+            if stop_tokens.contains(&next) {
+                break;
+            }
+            position += input.len();
+            input = vec![next];
+            generated.push(next);
+
+            // Decoding the whole answer each time keeps characters that span
+            // several tokens intact; only complete text is passed on.
+            let mut decoded = tokenizer.decode(&generated, true).map_err(|e| e.to_string())?;
+            let stop_at = sampling.stop.iter().filter_map(|stop| decoded.find(stop.as_str())).min();
+            if let Some(stop_at) = stop_at {
+                decoded.truncate(stop_at);
+            }
+            if decoded.len() > text.len() && decoded.starts_with(text.as_str()) && !decoded.ends_with('\u{FFFD}') {
+                on_token(&decoded[text.len()..]);
+                text = decoded;
+            }
+            if stop_at.is_some() {
+                return Ok(Completion::text(text, Some(Usage::new(prompt_tokens.len() as u64, generated.len() as u64))));
+            }
+        }
+
+        let decoded = tokenizer.decode(&generated, true).map_err(|e| e.to_string())?;
+        if decoded.len() > text.len() && decoded.starts_with(text.as_str()) {
+            on_token(&decoded[text.len()..]);
+        }
+
+        Ok(Completion::text(decoded, Some(Usage::new(prompt_tokens.len() as u64, generated.len() as u64))))
+    }
+}
+
+/// A readable error for a GGUF path that can't be used, checked by `--config`.
+pub fn check_files(gguf_path: &Path, tokenizer_path: Option<&Path>) -> Result<(), String> {
+    if !gguf_path.is_file() {
+        return Err(format!("{} does not exist", gguf_path.display()));
+    }
+    let provider = LocalProvider { gguf_path: gguf_path.to_path_buf(), tokenizer_path: tokenizer_path.map(Path::to_path_buf) };
+    let tokenizer_path = provider.tokenizer_path();
+    if !tokenizer_path.is_file() {
+        return Err(format!("{} does not exist; local models need the tokenizer.json that belongs to them", tokenizer_path.display()));
+    }
+    Ok(())
+}
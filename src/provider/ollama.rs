@@ -0,0 +1,333 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::env;
+use std::sync::OnceLock;
+use std::time::Duration;
+use crate::http::{self, HttpError, HttpErrorKind};
+use crate::interrupt::{self, Cancelled};
+use crate::sampling::DEFAULT_TEMPERATURE;
+use crate::usage::Usage;
+use super::{ChatRequest, Completion, Provider};
+
+const OLLAMA_DEFAULT_HOST: &str = "localhost:11434";
+const OLLAMA_DEFAULT_PORT: u16 = 11434;
+
+/// A local Ollama daemon, reached through its native API (`/api/chat`), which
+/// unlike the OpenAI-compatible one accepts `keep_alive` and model `options`.
+pub struct OllamaProvider {
+    pub model_name: String,
+}
+
+/// Generation settings for every Ollama model, stored under `ollama` in `config.json`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct OllamaSettings {
+    /// How long the daemon keeps the model loaded after a request: a duration
+    /// such as `"10m"`, or a number of seconds (`-1` keeps it loaded).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<Value>,
+    /// Model options passed through as they are, e.g. `num_ctx` and `seed`.
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub options: Map<String, Value>,
+}
+
+static SETTINGS: OnceLock<OllamaSettings> = OnceLock::new();
+
+/// Applies the settings from the config file. Only the first call has any effect.
+pub fn configure(settings: OllamaSettings) {
+    let _ = SETTINGS.set(settings);
+}
+
+/// A model installed in the daemon, from `/api/tags`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct InstalledModel {
+    pub name: String,
+    /// Size on disk in bytes.
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub details: ModelDetails,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ModelDetails {
+    pub parameter_size: Option<String>,
+    pub quantization_level: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TagsResponse {
+    #[serde(default)]
+    models: Vec<InstalledModel>,
+}
+
+/// One status line of a streamed `/api/pull`.
+#[derive(Deserialize, Debug)]
+pub struct PullProgress {
+    pub status: String,
+    /// Bytes in the layer being downloaded, and how many have arrived.
+    pub total: Option<u64>,
+    pub completed: Option<u64>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ChatBody<'a> {
+    model: String,
+    messages: Vec<Message<'a>>,
+    stream: bool,
+    /// A JSON schema the answer must follow.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<&'a Value>,
+    options: Map<String, Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<&'a Value>,
+}
+
+#[derive(Serialize)]
+struct Message<'a> {
+    role: &'static str,
+    content: &'a str,
+}
+
+/// The whole answer, or one chunk of a streamed one. The token counts come with the last.
+#[derive(Deserialize)]
+struct ChatChunk {
+    message: Option<ChunkMessage>,
+    #[serde(default)]
+    done: bool,
+    #[serde(default)]
+    prompt_eval_count: u64,
+    #[serde(default)]
+    eval_count: u64,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChunkMessage {
+    #[serde(default)]
+    content: String,
+}
+
+/// Resolves the daemon's base URL from `OLLAMA_HOST` the way the `ollama`
+/// CLI does.
+pub fn ollama_base_url() -> String {
+    base_url_from(&env::var("OLLAMA_HOST").unwrap_or_default())
+}
+
+/// `host` may be empty, a bare host, `host:port`, or a full URL, possibly
+/// with a path. Without a scheme, plain http on Ollama's port is assumed; a
+/// URL without a port keeps its scheme's default one.
+fn base_url_from(host: &str) -> String {
+    let host = host.trim().trim_end_matches('/');
+    let host = if host.is_empty() { OLLAMA_DEFAULT_HOST } else { host };
+
+    let scheme_given = host.contains("://");
+    let (scheme, rest) = host.split_once("://").unwrap_or(("http", host));
+    let (authority, path) = match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, ""),
+    };
+
+    // A port is present if the text after the last ':' is numeric (and not inside an IPv6 literal).
+    let has_port = authority.rsplit_once(':')
+        .is_some_and(|(_, port)| !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()));
+
+    if has_port || scheme_given {
+        format!("{}://{}{}", scheme, authority, path)
+    } else {
+        format!("{}://{}:{}{}", scheme, authority, OLLAMA_DEFAULT_PORT, path)
+    }
+}
+
+/// Points out a daemon that isn't running, which is by far the most common
+/// reason a request to it fails, instead of reporting a bare connection error.
+fn explain(mut error: HttpError) -> HttpError {
+    if error.kind == HttpErrorKind::Connect {
+        error.context = format!(
+            "Ollama isn't running at {} (start it with `ollama serve`, or set OLLAMA_HOST to where it runs)",
+            ollama_base_url(),
+        );
+    }
+    error
+}
+
+/// Models installed in the daemon.
+pub fn installed_models() -> Result<Vec<InstalledModel>, Box<dyn std::error::Error>> {
+    let client = http::client()?;
+    let url = format!("{}/api/tags", ollama_base_url());
+    let response = http::send_with_retry("Failed to list Ollama models", || client.get(&url)).map_err(explain)?;
+    let tags: TagsResponse = response.json()?;
+    Ok(tags.models)
+}
+
+/// Downloads a model into the daemon, passing each status update to `on_progress`.
+pub fn pull_model(model_name: &str, mut on_progress: impl FnMut(&PullProgress)) -> Result<(), Box<dyn std::error::Error>> {
+    let client = http::client()?;
+    let url = format!("{}/api/pull", ollama_base_url());
+    let body = json!({ "model": model_name, "stream": true });
+    let context = format!("Failed to pull Ollama model {}", model_name);
+    // Downloads take as long as they take; the configured read timeout is meant for generations.
+    let response = http::send_with_retry(&context, || client.post(&url).json(&body).timeout(Duration::from_secs(24 * 60 * 60)))
+        .map_err(explain)?;
+
+    read_json_lines(response, |line| {
+        let progress: PullProgress = serde_json::from_str(line)?;
+        if let Some(error) = &progress.error {
+            return Err(format!("{}: {}", context, error).into());
+        }
+        on_progress(&progress);
+        Ok(progress.status != "success")
+    })
+}
+
+/// Reads a newline-delimited JSON body, handing each line to `on_line` until
+/// the body ends, `on_line` returns `false`, or the user presses Ctrl-C.
+fn read_json_lines(
+    response: reqwest::blocking::Response,
+    mut on_line: impl FnMut(&str) -> Result<bool, Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for line in interrupt::lines(response) {
+        let line = line?;
+        if !line.trim().is_empty() && !on_line(&line)? {
+            return Ok(());
+        }
+    }
+    if interrupt::cancelled() {
+        return Err(Box::new(Cancelled));
+    }
+    Ok(())
+}
+
+impl OllamaProvider {
+    fn send_chat(&self, request: &ChatRequest, stream: bool) -> Result<reqwest::blocking::Response, Box<dyn std::error::Error>> {
+        let settings = SETTINGS.get_or_init(OllamaSettings::default);
+
+        let mut options = settings.options.clone();
+        options.entry("num_predict").or_insert_with(|| json!(request.max_tokens));
+        // Sampling parameters from the config or the command line take precedence over `options`.
+        let sampling = &request.sampling;
+        let overrides = [
+            ("temperature", sampling.temperature.map(|value| json!(value))),
+            ("top_p", sampling.top_p.map(|value| json!(value))),
+            ("stop", (!sampling.stop.is_empty()).then(|| json!(sampling.stop))),
+            ("frequency_penalty", sampling.frequency_penalty.map(|value| json!(value))),
+            ("presence_penalty", sampling.presence_penalty.map(|value| json!(value))),
+            ("seed", sampling.seed.map(|value| json!(value))),
+        ];
+        for (name, value) in overrides {
+            if let Some(value) = value {
+                options.insert(name.to_string(), value);
+            }
+        }
+        options.entry("temperature").or_insert_with(|| json!(DEFAULT_TEMPERATURE));
+
+        let body = ChatBody {
+            model: self.model_name(),
+            messages: vec![
+                Message { role: "system", content: request.system_prompt },
+                Message { role: "user", content: request.user_prompt },
+            ],
+            stream,
+            format: request.response_schema,
+            options,
+            keep_alive: settings.keep_alive.as_ref(),
+        };
+
+        let client = http::client()?;
+        let url = format!("{}/api/chat", ollama_base_url());
+        let response = http::send_with_retry(&format!("API Error for model {}", self.model_name), || client.post(&url).json(&body))
+            .map_err(|mut error| {
+                if error.kind == HttpErrorKind::Status(reqwest::StatusCode::NOT_FOUND) {
+                    error.context = format!(
+                        "Ollama model {} is not installed (pull it with `ollama pull {}`, or choose it in `llm-term --config`)",
+                        self.model_name, self.model_name,
+                    );
+                }
+                explain(error)
+            })?;
+        Ok(response)
+    }
+}
+
+impl Provider for OllamaProvider {
+    fn model_name(&self) -> String {
+        self.model_name.clone()
+    }
+
+    fn endpoint(&self) -> String {
+        format!("{}/api/", ollama_base_url())
+    }
+
+    // Ollama doesn't authenticate.
+    fn api_key(&self) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(String::new())
+    }
+
+    fn complete(&self, request: &ChatRequest) -> Result<Option<Completion>, Box<dyn std::error::Error>> {
+        let response: ChatChunk = self.send_chat(request, false)?.json()?;
+        if let Some(error) = response.error {
+            return Err(format!("API Error for model {}: {}", self.model_name, error).into());
+        }
+        let text = response.message.map(|message| message.content).unwrap_or_default();
+        Ok(Some(Completion::text(text, Some(Usage::new(response.prompt_eval_count, response.eval_count)))))
+    }
+
+    fn complete_streaming(&self, request: &ChatRequest, on_token: &mut dyn FnMut(&str)) -> Result<Option<Completion>, Box<dyn std::error::Error>> {
+        let response = self.send_chat(request, true)?;
+
+        let mut text = String::new();
+        let mut usage = None;
+        read_json_lines(response, |line| {
+            let chunk: ChatChunk = serde_json::from_str(line)?;
+            if let Some(error) = chunk.error {
+                return Err(format!("API Error for model {}: {}", self.model_name, error).into());
+            }
+            if let Some(message) = chunk.message.filter(|message| !message.content.is_empty()) {
+                on_token(&message.content);
+                text.push_str(&message.content);
+            }
+            if chunk.done {
+                usage = Some(Usage::new(chunk.prompt_eval_count, chunk.eval_count));
+            }
+            Ok(!chunk.done)
+        })?;
+
+        Ok(Some(Completion::text(text, usage)))
+    }
+
+    fn list_models(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        Ok(installed_models()?.into_iter().map(|model| model.name).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::base_url_from;
+
+    /// `OLLAMA_HOST` values and the base URL they resolve to.
+    const HOSTS: &[(&str, &str)] = &[
+        ("", "http://localhost:11434"),
+        ("  ", "http://localhost:11434"),
+        ("gpu-box", "http://gpu-box:11434"),
+        ("gpu-box:8080", "http://gpu-box:8080"),
+        ("0.0.0.0", "http://0.0.0.0:11434"),
+        ("192.168.1.5:11435", "http://192.168.1.5:11435"),
+        ("[::1]", "http://[::1]:11434"),
+        ("[::1]:8080", "http://[::1]:8080"),
+        ("http://gpu-box", "http://gpu-box"),
+        ("https://gpu-box", "https://gpu-box"),
+        ("http://gpu-box:8080", "http://gpu-box:8080"),
+        ("https://gpu-box:8443/", "https://gpu-box:8443"),
+        ("https://example.com/ollama", "https://example.com/ollama"),
+        ("https://example.com:8443/ollama/", "https://example.com:8443/ollama"),
+        ("gpu-box/ollama", "http://gpu-box:11434/ollama"),
+        ("gpu-box:8080/ollama", "http://gpu-box:8080/ollama"),
+    ];
+
+    #[test]
+    fn resolves_ollama_host() {
+        for (host, expected) in HOSTS {
+            assert_eq!(base_url_from(host), *expected, "OLLAMA_HOST={:?}", host);
+        }
+    }
+}
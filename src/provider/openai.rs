@@ -0,0 +1,98 @@
+use super::{require_env, Provider};
+
+const OPENAI_API_URL: &str = "https://api.openai.com/v1/";
+
+/// OpenAI's hosted chat completions API.
+pub struct OpenAiProvider {
+    pub model_name: String,
+}
+
+/// Context windows and output limits of OpenAI's chat models, by model id
+/// prefix. `/v1/models` doesn't report them.
+const TOKEN_LIMITS: &[(&str, i32, i32)] = &[
+    ("chatgpt-4o", 128_000, 16_384),
+    ("gpt-3.5-turbo", 16_385, 4_096),
+    ("gpt-4", 8_192, 8_192),
+    ("gpt-4-turbo", 128_000, 4_096),
+    ("gpt-4.1", 1_047_576, 32_768),
+    ("gpt-4.5", 128_000, 16_384),
+    ("gpt-4o", 128_000, 16_384),
+    ("gpt-5", 400_000, 128_000),
+    ("o1", 200_000, 100_000),
+    ("o1-mini", 128_000, 65_536),
+    ("o3", 200_000, 100_000),
+    ("o4-mini", 200_000, 100_000),
+];
+
+/// The entry with the longest matching prefix, so dated snapshots such as
+/// `gpt-4o-2024-08-06` are covered.
+fn token_limits(model_id: &str) -> Option<&'static (&'static str, i32, i32)> {
+    TOKEN_LIMITS.iter()
+        .filter(|(prefix, _, _)| model_id.starts_with(prefix))
+        .max_by_key(|(prefix, _, _)| prefix.len())
+}
+
+/// Context window of a model.
+pub fn context_length(model_id: &str) -> Option<i32> {
+    token_limits(model_id).map(|(_, context_length, _)| *context_length)
+}
+
+/// Most tokens a model can answer with, which bounds `max_tokens`.
+pub fn max_output_tokens(model_id: &str) -> Option<i32> {
+    token_limits(model_id).map(|(_, _, max_output_tokens)| *max_output_tokens)
+}
+
+/// Whether a model from `/v1/models` can answer chat completions. The list
+/// also has embedding, speech, image and moderation models.
+pub fn is_chat_model(model_id: &str) -> bool {
+    const NOT_CHAT: &[&str] = &["audio", "realtime", "transcribe", "tts", "image", "search", "instruct"];
+    context_length(model_id).is_some() && !NOT_CHAT.iter().any(|kind| model_id.contains(kind))
+}
+
+/// Whether a model is one of OpenAI's reasoning models, which only accept
+/// the default sampling. Ids routed through OpenRouter (`openai/o3-mini`) count too.
+pub fn is_reasoning_model(model_id: &str) -> bool {
+    let model_id = model_id.rsplit('/').next().unwrap_or(model_id);
+    let o_series = model_id.starts_with('o') && model_id[1..].starts_with(|c: char| c.is_ascii_digit());
+    o_series || (model_id.starts_with("gpt-5") && !model_id.contains("chat"))
+}
+
+impl Provider for OpenAiProvider {
+    fn model_name(&self) -> String {
+        self.model_name.clone()
+    }
+
+    fn endpoint(&self) -> String {
+        OPENAI_API_URL.to_string()
+    }
+
+    fn supports_n(&self) -> bool {
+        true
+    }
+
+    fn supports_logprobs(&self) -> bool {
+        true
+    }
+
+    fn uses_max_completion_tokens(&self) -> bool {
+        true
+    }
+
+    fn api_key(&self) -> Result<String, Box<dyn std::error::Error>> {
+        require_env("OPENAI_API_KEY")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{context_length, max_output_tokens};
+
+    #[test]
+    fn looks_up_token_limits_by_prefix() {
+        assert_eq!((context_length("gpt-4o-2024-08-06"), max_output_tokens("gpt-4o-2024-08-06")), (Some(128_000), Some(16_384)));
+        assert_eq!(max_output_tokens("gpt-4-turbo-preview"), Some(4_096));
+        assert_eq!(max_output_tokens("o1-mini-2024-09-12"), Some(65_536));
+        assert_eq!(max_output_tokens("gpt-4.1-nano"), Some(32_768));
+        assert_eq!(max_output_tokens("davinci-002"), None);
+    }
+}
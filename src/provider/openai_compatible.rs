@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+use crate::chat_client::ChatClient;
+use super::{require_env, Provider};
+
+/// Any server implementing the OpenAI chat completions API, such as vLLM,
+/// LM Studio or llama.cpp's server.
+pub struct OpenAiCompatibleProvider {
+    pub base_url: String,
+    pub model_name: String,
+    pub api_key_env: Option<String>,
+    pub extra_headers: HashMap<String, String>,
+}
+
+impl Provider for OpenAiCompatibleProvider {
+    fn model_name(&self) -> String {
+        self.model_name.clone()
+    }
+
+    fn endpoint(&self) -> String {
+        self.base_url.trim_end_matches('/').to_string()
+    }
+
+    // Many local servers run without authentication, so the key is optional.
+    fn api_key(&self) -> Result<String, Box<dyn std::error::Error>> {
+        match &self.api_key_env {
+            Some(var) => require_env(var),
+            None => Ok(String::new()),
+        }
+    }
+
+    // Support for `response_format` varies between servers and versions, so
+    // structured mode relies on the prompt and the tolerant parser here.
+    fn supports_json_schema(&self) -> bool {
+        false
+    }
+
+    // Not every server accepts `stream_options`; streamed answers go unmetered instead.
+    fn supports_stream_usage(&self) -> bool {
+        false
+    }
+
+    fn chat_client(&self) -> Result<ChatClient, Box<dyn std::error::Error>> {
+        let mut client = ChatClient::new(&self.endpoint())?;
+        let api_key = self.api_key()?;
+        if !api_key.is_empty() {
+            client = client.bearer_auth(&api_key);
+        }
+        for (name, value) in &self.extra_headers {
+            client = client.header(name, value);
+        }
+        Ok(client)
+    }
+}
@@ -0,0 +1,92 @@
+use serde_json::{json, Map, Value};
+use super::{require_env, Provider};
+use crate::chat_client::ChatClient;
+use crate::openrouter_client::{fetch_openrouter_models, openrouter_base_url, DataCollection, ProviderPreferences, APP_TITLE, APP_URL};
+
+/// OpenRouter, which proxies many hosted models behind an OpenAI-compatible API.
+pub struct OpenRouterProvider {
+    pub model_name: String,
+    pub provider: Option<ProviderPreferences>,
+    /// Models OpenRouter tries, in order, when `model_name` is unavailable.
+    pub models: Vec<String>,
+}
+
+impl Provider for OpenRouterProvider {
+    fn model_name(&self) -> String {
+        self.model_name.clone()
+    }
+
+    fn endpoint(&self) -> String {
+        openrouter_base_url()
+    }
+
+    fn api_key(&self) -> Result<String, Box<dyn std::error::Error>> {
+        require_env("OPENROUTER_API_KEY")
+    }
+
+    // Passed through to the upstream model; models without logprobs simply omit them.
+    fn supports_logprobs(&self) -> bool {
+        true
+    }
+
+    fn extra_body(&self) -> Map<String, Value> {
+        let mut extra = Map::new();
+        // Prompts only go to providers that don't store or train on them,
+        // unless the config explicitly sets `"data_collection": "allow"`.
+        let mut provider = self.provider.clone().unwrap_or_default();
+        provider.data_collection.get_or_insert(DataCollection::Deny);
+        extra.insert("provider".to_string(), json!(provider));
+        if !self.models.is_empty() {
+            extra.insert("models".to_string(), json!(self.models));
+        }
+        extra
+    }
+
+    // Attribution headers recommended by OpenRouter.
+    fn chat_client(&self) -> Result<ChatClient, Box<dyn std::error::Error>> {
+        Ok(ChatClient::new(&self.endpoint())?
+            .bearer_auth(&self.api_key()?)
+            .header("HTTP-Referer", APP_URL)
+            .header("X-Title", APP_TITLE))
+    }
+
+    fn list_models(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let models = fetch_openrouter_models(&self.api_key()?)?;
+        Ok(models.into_iter().map(|model| model.id).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::provider::Provider;
+    use crate::sampling::SamplingSettings;
+    use crate::test_server::{self, ENVIRONMENT};
+    use super::OpenRouterProvider;
+
+    #[test]
+    fn denies_data_collection_unless_allowed() {
+        let _environment = ENVIRONMENT.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let response = json!({
+            "id": "gen-1",
+            "model": "mistralai/mistral-7b-instruct",
+            "choices": [{ "index": 0, "message": { "role": "assistant", "content": "ls -la" }, "finish_reason": "stop" }],
+        });
+        let captured = test_server::serve_backend("/api/v1", "OPENROUTER_API_KEY", 200, "application/json", response.to_string());
+
+        let provider = OpenRouterProvider {
+            model_name: "meta-llama/llama-3.1-70b-instruct".to_string(),
+            provider: None,
+            models: vec!["mistralai/mistral-7b-instruct".to_string()],
+        };
+        let completion = provider.complete(&test_server::chat_request(SamplingSettings::default())).unwrap().unwrap();
+        let captured = captured.recv().unwrap();
+
+        assert_eq!(captured.path, "/api/v1/chat/completions");
+        assert_eq!(captured.headers["authorization"], "Bearer test-key");
+        assert_eq!(captured.body["provider"], json!({ "data_collection": "deny" }));
+        assert_eq!(captured.body["models"], json!(["mistralai/mistral-7b-instruct"]));
+        assert_eq!(completion.text, "ls -la");
+        assert_eq!(completion.model.as_deref(), Some("mistralai/mistral-7b-instruct"));
+    }
+}
@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+
+// Sampling parameters. They can be set for every model under `sampling` in
+// `config.json`, per model under `model_sampling`, and for a single run on
+// the command line; each level only overrides the fields it sets.
+
+/// Temperature when nothing else is configured.
+pub const DEFAULT_TEMPERATURE: f32 = 0.5;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SamplingSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    /// Sequences that end the answer.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    /// Makes answers reproducible where the backend supports it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+}
+
+impl SamplingSettings {
+    pub fn is_empty(&self) -> bool {
+        self.temperature.is_none()
+            && self.top_p.is_none()
+            && self.stop.is_empty()
+            && self.frequency_penalty.is_none()
+            && self.presence_penalty.is_none()
+            && self.seed.is_none()
+    }
+
+    /// The configured temperature, or the default.
+    pub fn temperature(&self) -> f32 {
+        self.temperature.unwrap_or(DEFAULT_TEMPERATURE)
+    }
+
+    /// These settings, with the fields that `overrides` sets replaced.
+    pub fn overridden_by(&self, overrides: &SamplingSettings) -> SamplingSettings {
+        SamplingSettings {
+            temperature: overrides.temperature.or(self.temperature),
+            top_p: overrides.top_p.or(self.top_p),
+            stop: if overrides.stop.is_empty() { self.stop.clone() } else { overrides.stop.clone() },
+            frequency_penalty: overrides.frequency_penalty.or(self.frequency_penalty),
+            presence_penalty: overrides.presence_penalty.or(self.presence_penalty),
+            seed: overrides.seed.or(self.seed),
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use std::fmt;
+
+// Cleans up what models actually send back before it gets anywhere near a
+// shell: reasoning blocks, markdown fences, copied prompts and trailing
+// explanations are stripped, and anything that still isn't a single clean
+// command is refused rather than guessed at.
+
+const REASONING_TAGS: [&str; 3] = ["think", "thinking", "reasoning"];
+
+/// Prompt prefixes models copy from transcripts, e.g. `$ ls` or `PS> dir`.
+const PROMPT_PREFIXES: [&str; 4] = ["$ ", "% ", "❯ ", "PS> "];
+
+/// Openers that mark a line as explanation rather than command.
+const PROSE_OPENERS: [&str; 18] = [
+    "Explanation", "Note:", "Note that", "This ", "The ", "Here ", "Here's", "It ", "You ", "Replace ",
+    "Make sure", "If you", "Alternatively", "Or, ", "Where ", "I'm ", "I cannot", "I can't",
+];
+
+/// Returned when the model answered, but the answer can't safely be offered for execution.
+#[derive(Debug)]
+pub struct UnusableOutput {
+    pub reason: String,
+    pub raw: String,
+}
+
+impl fmt::Display for UnusableOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "model output is not a single clean command: {}", self.reason)
+    }
+}
+
+impl std::error::Error for UnusableOutput {}
+
+/// Reduces raw model output to the command it contains.
+///
+/// Returns `Ok(None)` when there is nothing to run (the model returned an
+/// empty answer, or only reasoning or comments), and `Err` when the answer has
+/// content that can't be reduced to exactly one command.
+pub fn sanitize_command(raw: &str) -> Result<Option<String>, UnusableOutput> {
+    let reject = |reason: &str| UnusableOutput { reason: reason.to_string(), raw: raw.to_string() };
+
+    let text = strip_reasoning(raw);
+    let text = extract_fenced_block(&text).map_err(reject)?;
+
+    let mut lines: Vec<String> = Vec::new();
+    let mut saw_blank_after_command = false;
+    let mut heredoc_delimiter: Option<String> = None;
+    for line in text.lines() {
+        // Heredoc bodies are data, not commands or prose, and are kept verbatim.
+        if let Some(delimiter) = &heredoc_delimiter {
+            if line.trim() == delimiter {
+                heredoc_delimiter = None;
+            }
+            lines.push(line.trim_end().to_string());
+            continue;
+        }
+
+        let line = strip_prompt_prefix(line.trim());
+
+        if line.is_empty() {
+            saw_blank_after_command = !lines.is_empty();
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+        if looks_like_prose(line) {
+            if lines.is_empty() && line.ends_with(':') {
+                // A lead-in such as "Here is the command:".
+                continue;
+            }
+            if lines.is_empty() {
+                return Err(reject("the answer is an explanation, not a command"));
+            }
+            // Everything from here on explains the command above it.
+            break;
+        }
+        if saw_blank_after_command || lines.last().is_some_and(|previous| !continues_on_next_line(previous, line)) {
+            return Err(reject("the answer contains more than one command"));
+        }
+
+        lines.push(line.to_string());
+        heredoc_delimiter = heredoc_delimiter_of(line);
+    }
+
+    if lines.is_empty() {
+        return Ok(None);
+    }
+
+    let command = unwrap_backticks(&lines.join("\n")).to_string();
+    if command.contains("```") {
+        return Err(reject("the answer contains unbalanced markdown fences"));
+    }
+
+    Ok(if command.is_empty() { None } else { Some(command) })
+}
+
+/// Removes `<think>…</think>`-style blocks. An unterminated block swallows the
+/// rest of the answer, and a stray closing tag discards everything before it.
+pub fn strip_reasoning(raw: &str) -> String {
+    let mut text = raw.to_string();
+
+    for tag in REASONING_TAGS {
+        let open = format!("<{}>", tag);
+        let close = format!("</{}>", tag);
+
+        loop {
+            // Tags are ASCII, so byte offsets in the lowercased copy line up with the original.
+            let lower = text.to_ascii_lowercase();
+            match (lower.find(&open), lower.find(&close)) {
+                (Some(start), Some(end)) if end > start => {
+                    text.replace_range(start..end + close.len(), "");
+                }
+                (Some(start), _) => {
+                    text.truncate(start);
+                }
+                (None, Some(end)) => {
+                    text.replace_range(..end + close.len(), "");
+                }
+                (None, None) => break,
+            }
+        }
+    }
+
+    text
+}
+
+/// If the answer contains markdown fences, keeps only the fenced content.
+fn extract_fenced_block(text: &str) -> Result<String, &'static str> {
+    let trimmed = text.trim();
+
+    // ```ls -la``` on a single line.
+    if !trimmed.contains('\n') && trimmed.len() > 6 && trimmed.starts_with("```") && trimmed.ends_with("```") {
+        return Ok(trimmed[3..trimmed.len() - 3].to_string());
+    }
+
+    let mut blocks: Vec<String> = Vec::new();
+    let mut current: Option<Vec<&str>> = None;
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            match current.take() {
+                Some(block) => blocks.push(block.join("\n")),
+                None => current = Some(Vec::new()),
+            }
+        } else if let Some(block) = current.as_mut() {
+            block.push(line);
+        }
+    }
+    // A reply cut off by max_tokens may never close its fence.
+    if let Some(block) = current {
+        blocks.push(block.join("\n"));
+    }
+
+    if blocks.is_empty() {
+        return Ok(text.to_string());
+    }
+
+    blocks.retain(|block| !block.trim().is_empty());
+    blocks.dedup_by(|a, b| a.trim() == b.trim());
+    match blocks.len() {
+        0 => Ok(String::new()),
+        1 => Ok(blocks.remove(0)),
+        _ => Err("the answer contains several code blocks"),
+    }
+}
+
+fn strip_prompt_prefix(line: &str) -> &str {
+    for prefix in PROMPT_PREFIXES {
+        if let Some(rest) = line.strip_prefix(prefix) {
+            return rest.trim_start();
+        }
+    }
+    // PowerShell prompts that include the location, e.g. `PS C:\Users\me> dir`.
+    if line.starts_with("PS ") {
+        if let Some((_, rest)) = line.split_once("> ") {
+            return rest.trim_start();
+        }
+    }
+    line
+}
+
+fn looks_like_prose(line: &str) -> bool {
+    if PROSE_OPENERS.iter().any(|opener| line.starts_with(opener)) {
+        return true;
+    }
+
+    // A capitalised sentence with no shell syntax in it.
+    let words = line.split_whitespace().count();
+    let starts_capitalised = line.chars().next().is_some_and(|c| c.is_uppercase());
+    let has_shell_syntax = line.chars().any(|c| matches!(c, '|' | '>' | '<' | '$' | ';' | '&' | '=' | '/' | '\\' | '-' | '*'));
+    starts_capitalised && words >= 4 && (line.ends_with('.') || line.ends_with(':')) && !has_shell_syntax
+}
+
+/// Whether two lines are part of the same command rather than two commands.
+fn continues_on_next_line(line: &str, next: &str) -> bool {
+    let line = line.trim_end();
+    let next = next.trim_start();
+
+    let first_word = next.split_whitespace().next().unwrap_or("").trim_end_matches(';');
+
+    [r"\", "&&", "||", "|", "{", "(", " do", " then", " else", "`"].iter().any(|suffix| line.ends_with(suffix))
+        || ["do", "then", "else", "elif", "fi", "done", "esac"].contains(&first_word)
+        || ["}", ")", "&&", "||", "|"].iter().any(|prefix| next.starts_with(prefix))
+}
+
+/// The terminator of a heredoc started on this line, e.g. `EOF` for `cat <<'EOF' > notes.txt`.
+fn heredoc_delimiter_of(line: &str) -> Option<String> {
+    let (_, rest) = line.split_once("<<")?;
+    // `<<<` is a here-string, which doesn't span lines.
+    if rest.starts_with('<') {
+        return None;
+    }
+    let word = rest.trim_start_matches('-').split_whitespace().next()?;
+    let delimiter = word.trim_matches(|c| c == '\'' || c == '"');
+    if delimiter.is_empty() { None } else { Some(delimiter.to_string()) }
+}
+
+/// Strips a single pair of backticks wrapping the whole command. Quotes are
+/// left alone: a command that is one quoted path needs them.
+fn unwrap_backticks(command: &str) -> &str {
+    let command = command.trim();
+    if command.len() >= 2 && command.starts_with('`') && command.ends_with('`') {
+        let inner = &command[1..command.len() - 1];
+        if !inner.contains('`') {
+            return inner.trim();
+        }
+    }
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sanitize_command;
+
+    enum Expected {
+        Command(&'static str),
+        Nothing,
+        Rejected,
+    }
+    use Expected::*;
+
+    /// Raw answers as models have sent them, and what should be made of them.
+    const FIXTURES: &[(&str, &str, Expected)] = &[
+        ("plain command", "ls -la", Command("ls -la")),
+        ("surrounding whitespace", "  \n ls -la \n\n", Command("ls -la")),
+        ("empty answer", "", Nothing),
+        ("fence without a language", "```\nls -la\n```", Command("ls -la")),
+        ("fence with a language", "```bash\nfind . -name '*.rs'\n```", Command("find . -name '*.rs'")),
+        ("single-line fence", "```ls -la```", Command("ls -la")),
+        ("fence cut off by max_tokens", "```sh\ndu -sh *", Command("du -sh *")),
+        ("inline backticks", "`pwd`", Command("pwd")),
+        ("double-quoted path with spaces", "\"C:\\Program Files\\app.exe\"", Command("\"C:\\Program Files\\app.exe\"")),
+        ("single-quoted script with a space", "'my script.sh'", Command("'my script.sh'")),
+        ("quoted path in backticks", "`'my script.sh'`", Command("'my script.sh'")),
+        ("dollar prompt", "$ df -h", Command("df -h")),
+        ("powershell prompt", "PS C:\\> Get-ChildItem -Force", Command("Get-ChildItem -Force")),
+        ("powershell prompt with a path", "PS C:\\Users\\me> dir", Command("dir")),
+        ("starship prompt", "❯ git status", Command("git status")),
+        ("closed think block", "<think>The user wants files.</think>\nls -la", Command("ls -la")),
+        ("closed reasoning block", "<reasoning>\nuse ps\n</reasoning>\nps aux", Command("ps aux")),
+        ("uppercase think tags", "<THINK>hmm</THINK>uname -a", Command("uname -a")),
+        ("unterminated think block", "<think>Let me consider which flags", Nothing),
+        ("command before an unterminated think block", "whoami\n<think>was that right", Command("whoami")),
+        ("stray closing tag", "I should list the files.\n</think>\nls -la", Command("ls -la")),
+        ("lead-in", "Here is the command:\nls -la", Command("ls -la")),
+        ("lead-in and fence", "Here is the command:\n\n```bash\nls -la\n```", Command("ls -la")),
+        ("trailing explanation", "tar -czf backup.tar.gz src\n\nThis creates a compressed archive of src.", Command("tar -czf backup.tar.gz src")),
+        ("explanation after a fence", "```\ndf -h\n```\nThe -h flag prints human-readable sizes.", Command("df -h")),
+        ("comment line", "# show disk usage\ndf -h", Command("df -h")),
+        ("only a comment", "# nothing to do", Nothing),
+        (
+            "heredoc",
+            "cat <<'EOF' > notes.txt\nfirst line\n\nThis is data, not prose.\nEOF",
+            Command("cat <<'EOF' > notes.txt\nfirst line\n\nThis is data, not prose.\nEOF"),
+        ),
+        ("backslash continuation", "docker run \\\n  -p 80:80 \\\n  nginx", Command("docker run \\\n-p 80:80 \\\nnginx")),
+        ("&& continuation", "mkdir build &&\ncd build", Command("mkdir build &&\ncd build")),
+        ("leading && continuation", "cargo build\n&& cargo test", Command("cargo build\n&& cargo test")),
+        ("pipe continuation", "ps aux |\ngrep nginx", Command("ps aux |\ngrep nginx")),
+        ("for loop", "for f in *.log; do\n  gzip \"$f\"\ndone", Command("for f in *.log; do\ngzip \"$f\"\ndone")),
+        ("two commands", "ls -la\npwd", Rejected),
+        ("two commands separated by a blank line", "ls -la\n\npwd", Rejected),
+        ("two code blocks", "```\nls\n```\nor\n```\nls -a\n```", Rejected),
+        ("the same code block twice", "```\nls\n```\n```\nls\n```", Command("ls")),
+        ("prose only", "I'm sorry, but I can't help with that.", Rejected),
+        ("prose sentence", "You should use the find command for this.", Rejected),
+        ("unbalanced fence", "ls ```", Rejected),
+    ];
+
+    #[test]
+    fn fixtures() {
+        for (name, raw, expected) in FIXTURES {
+            let actual = sanitize_command(raw);
+            match (expected, &actual) {
+                (Command(command), Ok(Some(actual))) => assert_eq!(actual, command, "{}", name),
+                (Nothing, Ok(None)) | (Rejected, Err(_)) => {}
+                _ => panic!("{}: unexpected result {:?} for {:?}", name, actual, raw),
+            }
+        }
+    }
+
+    #[test]
+    fn rejection_keeps_the_raw_answer() {
+        let raw = "ls -la\npwd";
+        let error = sanitize_command(raw).unwrap_err();
+        assert_eq!(error.raw, raw);
+        assert!(error.to_string().contains("more than one command"));
+    }
+}
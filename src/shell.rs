@@ -1,4 +1,5 @@
 /// Enum representing different types of shells.
+#[allow(clippy::enum_variant_names)]
 pub(crate) enum Shell {
     Powershell,
     BornAgainShell,
@@ -0,0 +1,36 @@
+use crate::interrupt::{self, Cancelled};
+
+/// Reads a server-sent events body, handing the `data` payload of each event
+/// to `on_data` until the stream ends, `on_data` returns `false`, or the user
+/// presses Ctrl-C.
+pub fn read_events(
+    response: reqwest::blocking::Response,
+    mut on_data: impl FnMut(&str) -> Result<bool, Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut data = String::new();
+
+    for line in interrupt::lines(response) {
+        let line = line?;
+
+        if line.is_empty() {
+            if !data.is_empty() && !on_data(&data)? {
+                return Ok(());
+            }
+            data.clear();
+        } else if let Some(value) = line.strip_prefix("data:") {
+            if !data.is_empty() {
+                data.push('\n');
+            }
+            data.push_str(value.strip_prefix(' ').unwrap_or(value));
+        }
+        // `event:`, `id:`, `retry:` and `:` comment lines carry nothing we need.
+    }
+
+    if interrupt::cancelled() {
+        return Err(Box::new(Cancelled));
+    }
+    if !data.is_empty() {
+        on_data(&data)?;
+    }
+    Ok(())
+}
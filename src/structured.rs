@@ -0,0 +1,248 @@
+use colored::*;
+use serde_json::{json, Map, Value};
+use std::fmt;
+use crate::sanitize::strip_reasoning;
+
+// Structured response mode: the model answers with a JSON object describing
+// the command instead of the bare command, so we can show what it does and
+// how risky it is before asking to run it.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RiskLevel {
+    Low,
+    Medium,
+    High,
+}
+
+impl fmt::Display for RiskLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RiskLevel::Low => write!(f, "low"),
+            RiskLevel::Medium => write!(f, "medium"),
+            RiskLevel::High => write!(f, "high"),
+        }
+    }
+}
+
+/// What the model told us about a command, besides the command itself.
+#[derive(Debug, Clone)]
+pub struct CommandDetails {
+    pub explanation: String,
+    pub risk_level: Option<RiskLevel>,
+    pub requires_sudo: bool,
+    pub confidence: Option<f64>,
+}
+
+impl CommandDetails {
+    /// Prints the explanation and a one-line risk summary, coloured by risk.
+    pub fn print(&self) {
+        if !self.explanation.is_empty() {
+            println!("{}", self.explanation.italic());
+        }
+
+        let mut summary = match self.risk_level {
+            Some(risk) => format!("Risk: {}", risk),
+            None => "Risk: unknown".to_string(),
+        };
+        if self.requires_sudo {
+            summary.push_str(" | requires sudo");
+        }
+        if let Some(confidence) = self.confidence {
+            summary.push_str(&format!(" | confidence {:.0}%", confidence * 100.0));
+        }
+
+        let summary = match self.risk_level {
+            Some(RiskLevel::Low) => summary.green(),
+            Some(RiskLevel::Medium) | None => summary.yellow(),
+            Some(RiskLevel::High) => summary.red().bold(),
+        };
+        println!("{}", summary);
+    }
+}
+
+/// JSON schema for the structured answer, in the strict form OpenAI's
+/// `response_format` expects.
+pub fn response_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "command": { "type": "string", "description": "The command to run, or an empty string if unsure." },
+            "explanation": { "type": "string", "description": "One or two sentences on what the command does." },
+            "risk_level": { "type": "string", "enum": ["low", "medium", "high"] },
+            "requires_sudo": { "type": "boolean" },
+            "confidence": { "type": "number", "description": "Between 0 and 1." }
+        },
+        "required": ["command", "explanation", "risk_level", "requires_sudo", "confidence"],
+        "additionalProperties": false
+    })
+}
+
+/// Instructions appended to the system prompt in structured mode, for
+/// backends that can't enforce the schema themselves.
+pub fn prompt_suffix() -> &'static str {
+    "Respond with a single JSON object and nothing else, with these fields: \"command\" (string, the command, \
+     following all rules above, or an empty string if you are not confident), \"explanation\" (string, one or two \
+     sentences on what it does), \"risk_level\" (\"low\", \"medium\" or \"high\" - high if it deletes or overwrites data, \
+     changes system configuration or is hard to undo), \"requires_sudo\" (boolean) and \"confidence\" (number from 0 to 1)."
+}
+
+/// Pulls the structured answer out of raw model output. Tolerates markdown
+/// fences, text around the object, misnamed fields and numbers sent as
+/// strings; returns `None` if no JSON object with a `command` field is found.
+pub fn parse_structured(raw: &str) -> Option<(String, CommandDetails)> {
+    let text = strip_reasoning(raw);
+    let (object, command) = json_objects(&text)
+        .find_map(|object| string_field(&object, &["command", "cmd"]).map(|command| (object, command)))?;
+
+    let explanation = string_field(&object, &["explanation", "description", "reason"]).unwrap_or_default();
+    let risk_level = string_field(&object, &["risk_level", "risk", "riskLevel"])
+        .and_then(|risk| match risk.trim().to_lowercase().as_str() {
+            "low" => Some(RiskLevel::Low),
+            "medium" | "moderate" => Some(RiskLevel::Medium),
+            "high" | "critical" => Some(RiskLevel::High),
+            _ => None,
+        });
+    let requires_sudo = field(&object, &["requires_sudo", "sudo", "requiresSudo"])
+        .map(|value| match value {
+            Value::Bool(flag) => *flag,
+            Value::String(text) => matches!(text.trim().to_lowercase().as_str(), "true" | "yes"),
+            _ => false,
+        })
+        .unwrap_or(false);
+    let confidence = field(&object, &["confidence"])
+        .and_then(|value| match value {
+            Value::Number(number) => number.as_f64(),
+            Value::String(text) => text.trim().trim_end_matches('%').parse::<f64>().ok(),
+            _ => None,
+        })
+        // Some models answer in percent.
+        .map(|confidence| if confidence > 1.0 { confidence / 100.0 } else { confidence })
+        .map(|confidence| confidence.clamp(0.0, 1.0));
+
+    Some((command, CommandDetails { explanation, risk_level, requires_sudo, confidence }))
+}
+
+/// The parseable JSON objects in the text, in order, starting at each `{`.
+fn json_objects(raw: &str) -> impl Iterator<Item = Map<String, Value>> + '_ {
+    raw.match_indices('{').filter_map(|(start, _)| {
+        let mut stream = serde_json::Deserializer::from_str(&raw[start..]).into_iter::<Value>();
+        match stream.next() {
+            Some(Ok(Value::Object(object))) => Some(object),
+            _ => None,
+        }
+    })
+}
+
+fn field<'a>(object: &'a Map<String, Value>, names: &[&str]) -> Option<&'a Value> {
+    names.iter().find_map(|name| object.get(*name))
+}
+
+fn string_field(object: &Map<String, Value>, names: &[&str]) -> Option<String> {
+    match field(object, names)? {
+        Value::String(text) => Some(text.clone()),
+        Value::Null => None,
+        other => Some(other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_structured, RiskLevel};
+
+    /// What should be made of an answer: its command, explanation, risk,
+    /// whether it needs sudo and the confidence.
+    type Parsed = (&'static str, &'static str, Option<RiskLevel>, bool, Option<f64>);
+
+    /// Raw structured answers as models send them, and what should be made of them.
+    const FIXTURES: &[(&str, &str, Option<Parsed>)] = &[
+        (
+            "strict object",
+            r#"{"command": "ls -la", "explanation": "Lists all files.", "risk_level": "low", "requires_sudo": false, "confidence": 0.9}"#,
+            Some(("ls -la", "Lists all files.", Some(RiskLevel::Low), false, Some(0.9))),
+        ),
+        (
+            "fenced object",
+            "```json\n{\"command\": \"df -h\", \"risk_level\": \"low\"}\n```",
+            Some(("df -h", "", Some(RiskLevel::Low), false, None)),
+        ),
+        (
+            "text before and after the object",
+            "Sure, here you go:\n{\"command\": \"pwd\", \"explanation\": \"Prints the directory.\"}\nLet me know if you need more.",
+            Some(("pwd", "Prints the directory.", None, false, None)),
+        ),
+        (
+            "braces in the prose before the object",
+            "Use {} with find's -exec. {\"command\": \"find . -name '*.log' -exec rm {} +\", \"risk_level\": \"high\"}",
+            Some(("find . -name '*.log' -exec rm {} +", "", Some(RiskLevel::High), false, None)),
+        ),
+        (
+            "object inside a think block",
+            "<think>Maybe {\"command\": \"rm -rf build\"}? No, safer to list.</think>\n{\"command\": \"ls build\"}",
+            Some(("ls build", "", None, false, None)),
+        ),
+        ("object only inside a think block", "<think>{\"command\": \"ls\"}</think>", None),
+        ("object only inside an unterminated think block", "<think>{\"command\": \"ls\"}", None),
+        (
+            "alias field names",
+            r#"{"cmd": "sudo apt update", "description": "Refreshes package lists.", "riskLevel": "Moderate", "requiresSudo": true}"#,
+            Some(("sudo apt update", "Refreshes package lists.", Some(RiskLevel::Medium), true, None)),
+        ),
+        (
+            "percent confidence as a string",
+            r#"{"command": "uptime", "confidence": "85%"}"#,
+            Some(("uptime", "", None, false, Some(0.85))),
+        ),
+        (
+            "percent confidence as a number",
+            r#"{"command": "uptime", "confidence": 85}"#,
+            Some(("uptime", "", None, false, Some(0.85))),
+        ),
+        (
+            "confidence as a string",
+            r#"{"command": "uptime", "confidence": "0.7"}"#,
+            Some(("uptime", "", None, false, Some(0.7))),
+        ),
+        (
+            "confidence out of range",
+            r#"{"command": "uptime", "confidence": -3}"#,
+            Some(("uptime", "", None, false, Some(0.0))),
+        ),
+        (
+            "string booleans",
+            r#"{"command": "sudo reboot", "risk": "critical", "sudo": "Yes"}"#,
+            Some(("sudo reboot", "", Some(RiskLevel::High), true, None)),
+        ),
+        (
+            "unknown risk level",
+            r#"{"command": "ls", "risk_level": "none", "requires_sudo": "no"}"#,
+            Some(("ls", "", None, false, None)),
+        ),
+        ("empty command", r#"{"command": "", "confidence": 0.2}"#, Some(("", "", None, false, Some(0.2)))),
+        ("missing command", r#"{"explanation": "I'm not sure.", "confidence": 0.1}"#, None),
+        ("null command", r#"{"command": null, "explanation": "I'm not sure."}"#, None),
+        ("no object", "ls -la", None),
+        ("truncated object", r#"{"command": "ls -la", "explanation": "Lists"#, None),
+    ];
+
+    #[test]
+    fn fixtures() {
+        for (name, raw, expected) in FIXTURES {
+            let actual = parse_structured(raw);
+            match (expected, &actual) {
+                (Some((command, explanation, risk_level, requires_sudo, confidence)), Some((actual_command, details))) => {
+                    assert_eq!(actual_command, command, "{}", name);
+                    assert_eq!(details.explanation, *explanation, "{}", name);
+                    assert_eq!(details.risk_level, *risk_level, "{}", name);
+                    assert_eq!(details.requires_sudo, *requires_sudo, "{}", name);
+                    match (confidence, details.confidence) {
+                        (Some(expected), Some(actual)) => assert!((expected - actual).abs() < 1e-9, "{}: confidence {}", name, actual),
+                        (None, None) => {}
+                        _ => panic!("{}: unexpected confidence {:?}", name, details.confidence),
+                    }
+                }
+                (None, None) => {}
+                _ => panic!("{}: unexpected result {:?} for {:?}", name, actual, raw),
+            }
+        }
+    }
+}
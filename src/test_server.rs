@@ -0,0 +1,102 @@
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Mutex;
+use std::thread;
+use crate::provider::ChatRequest;
+use crate::sampling::SamplingSettings;
+
+// A one-shot HTTP server on a local port, for testing the backends' requests
+// and response parsing without their real APIs.
+
+/// The backends take their keys from the environment, which the tests
+/// share; hold this while setting and using them.
+pub static ENVIRONMENT: Mutex<()> = Mutex::new(());
+
+thread_local! {
+    static BASE_URL: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Sends this test's requests to `base_url` instead of the real API.
+pub fn redirect(base_url: String) {
+    BASE_URL.with(|url| *url.borrow_mut() = Some(base_url));
+}
+
+/// The base URL set by `redirect` for this test, if any.
+pub fn base_url() -> Option<String> {
+    BASE_URL.with(|url| url.borrow().clone())
+}
+
+/// The request the server received.
+pub struct Captured {
+    /// Path and query string.
+    pub path: String,
+    /// Header names in lower case.
+    pub headers: HashMap<String, String>,
+    /// The JSON body, or `Null` for a request without one.
+    pub body: Value,
+}
+
+/// Answers one request with `status` and `response`. Returns the server's
+/// `http://host:port` address and the request once it has been answered.
+pub fn serve_once(status: u16, content_type: &'static str, response: String) -> (String, Receiver<Captured>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = format!("http://{}", listener.local_addr().unwrap());
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+        let mut headers = HashMap::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            match line.trim_end().split_once(": ") {
+                Some((name, value)) => headers.insert(name.to_ascii_lowercase(), value.to_string()),
+                None => break,
+            };
+        }
+        let length = headers.get("content-length").map_or(0, |length| length.parse().unwrap());
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+
+        write!(
+            stream,
+            "HTTP/1.1 {} X\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status, content_type, response.len(), response,
+        ).unwrap();
+
+        let path = request_line.split_whitespace().nth(1).unwrap_or_default().to_string();
+        let body = if body.is_empty() { Value::Null } else { serde_json::from_slice(&body).unwrap() };
+        let _ = sender.send(Captured { path, headers, body });
+    });
+    (address, receiver)
+}
+
+/// Answers one request like `serve_once`, and points this test's backend at
+/// the server under `base_path`, with `test-key` in `api_key_variable`.
+pub fn serve_backend(base_path: &str, api_key_variable: &str, status: u16, content_type: &'static str, response: String) -> Receiver<Captured> {
+    let (address, captured) = serve_once(status, content_type, response);
+    redirect(format!("{}{}", address, base_path));
+    env::set_var(api_key_variable, "test-key");
+    captured
+}
+
+/// The request the backends' tests send.
+pub fn chat_request(sampling: SamplingSettings) -> ChatRequest<'static> {
+    ChatRequest {
+        system_prompt: "answer with a command",
+        user_prompt: "list files",
+        max_tokens: 64,
+        response_schema: None,
+        logprobs: false,
+        sampling,
+    }
+}
@@ -0,0 +1,230 @@
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::model::Model;
+use crate::openrouter_client;
+
+// Token usage ledger: every request appends a line to `usage.jsonl` next to
+// the binary, so what llm-term costs can be reported per day and per model.
+
+/// Token counts for one request, in the OpenAI `usage` shape. Other backends
+/// convert their own counters into it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct Usage {
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    #[serde(default)]
+    pub completion_tokens: u64,
+    #[serde(default)]
+    pub total_tokens: u64,
+}
+
+impl Usage {
+    pub fn new(prompt_tokens: u64, completion_tokens: u64) -> Self {
+        Usage { prompt_tokens, completion_tokens, total_tokens: prompt_tokens + completion_tokens }
+    }
+
+    pub fn add(&mut self, other: &Usage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+}
+
+/// One line of the ledger.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LedgerEntry {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub model: String,
+    /// The backend's request id; for OpenRouter, the generation id that
+    /// `llm-term openrouter generation` looks up.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generation_id: Option<String>,
+    #[serde(flatten)]
+    pub usage: Usage,
+    /// Cost in US dollars, where the provider publishes prices.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost_usd: Option<f64>,
+    /// Set for local models, which don't count towards the budget.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub free: bool,
+}
+
+pub fn get_ledger_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let exe_path = std::env::current_exe()?;
+    let exe_dir = exe_path.parent().ok_or("Failed to get executable directory")?;
+    Ok(exe_dir.join("usage.jsonl"))
+}
+
+/// Entries recorded during this run, for `--verbose`.
+static RECORDED: Mutex<Vec<LedgerEntry>> = Mutex::new(Vec::new());
+
+/// Appends a request's usage to the ledger. A ledger that can't be written is
+/// reported but never fails the request.
+/// `served_by` is the model id the backend says answered, if it differs from the configured one.
+pub fn record(model: &Model, usage: Usage, generation_id: Option<String>, served_by: Option<&str>) {
+    let entry = LedgerEntry {
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0),
+        model: model.to_string(),
+        generation_id,
+        usage,
+        cost_usd: cost(model, served_by, &usage),
+        free: !model.is_paid(),
+    };
+
+    if let Err(e) = append(&entry) {
+        eprintln!("{}", format!("Could not write the usage ledger: {}", e).yellow());
+    }
+    RECORDED.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(entry);
+}
+
+/// Prints the usage of the requests made since the last call. Called once the
+/// answer is complete, so the lines don't land in the middle of a stream.
+pub fn print_recorded() {
+    let recorded = std::mem::take(&mut *RECORDED.lock().unwrap_or_else(|poisoned| poisoned.into_inner()));
+    for entry in recorded {
+        let mut line = format!(
+            "{}: {} prompt + {} completion = {} tokens",
+            entry.model, entry.usage.prompt_tokens, entry.usage.completion_tokens, entry.usage.total_tokens,
+        );
+        if let Some(cost) = entry.cost_usd {
+            line.push_str(&format!(" (${:.6})", cost));
+        }
+        if let Some(id) = &entry.generation_id {
+            line.push_str(&format!(" [{}]", id));
+        }
+        eprintln!("{}", line.dimmed());
+    }
+}
+
+fn append(entry: &LedgerEntry) -> Result<(), Box<dyn std::error::Error>> {
+    // One write per line, so entries from parallel requests don't interleave.
+    let line = format!("{}\n", serde_json::to_string(entry)?);
+    let mut file = OpenOptions::new().create(true).append(true).open(get_ledger_path()?)?;
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Cost of a request for models with published per-token prices.
+fn cost(model: &Model, served_by: Option<&str>, usage: &Usage) -> Option<f64> {
+    match model {
+        Model::OpenRouter { model_name, .. } => {
+            openrouter_client::model_pricing(served_by.unwrap_or(model_name))?
+                .cost(usage.prompt_tokens, usage.completion_tokens)
+        }
+        _ => None,
+    }
+}
+
+/// Whether `cost` will know what requests to this model cost. Free models
+/// cost nothing; OpenRouter requests may be served by any of the fallback
+/// `models`, so all of them need prices in the cached catalogue.
+pub fn cost_is_known(model: &Model) -> bool {
+    match model {
+        Model::OpenRouter { model_name, models, .. } => std::iter::once(model_name)
+            .chain(models)
+            .all(|model_id| openrouter_client::model_pricing(model_id).is_some_and(|pricing| pricing.cost(0, 0).is_some())),
+        _ => !model.is_paid(),
+    }
+}
+
+pub fn load_ledger() -> Result<Vec<LedgerEntry>, Box<dyn std::error::Error>> {
+    let content = match fs::read_to_string(get_ledger_path()?) {
+        Ok(content) => content,
+        Err(_) => return Ok(Vec::new()),
+    };
+    // A line cut short by a crash shouldn't hide the rest of the ledger.
+    Ok(content.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+}
+
+#[derive(Default)]
+struct Totals {
+    requests: u64,
+    usage: Usage,
+    cost_usd: Option<f64>,
+    /// Whether some paid requests have no known cost, so `cost_usd` is a lower bound.
+    unpriced: bool,
+}
+
+impl Totals {
+    fn add(&mut self, entry: &LedgerEntry) {
+        self.requests += 1;
+        self.usage.add(&entry.usage);
+        match entry.cost_usd {
+            Some(cost) => *self.cost_usd.get_or_insert(0.0) += cost,
+            None if !entry.free => self.unpriced = true,
+            None => {}
+        }
+    }
+}
+
+/// Prints token and cost totals per day and per model.
+pub fn print_report() -> Result<(), Box<dyn std::error::Error>> {
+    let entries = load_ledger()?;
+    if entries.is_empty() {
+        println!("{}", "No usage recorded yet.".yellow());
+        return Ok(());
+    }
+
+    let mut per_day: BTreeMap<String, Totals> = BTreeMap::new();
+    let mut per_model: BTreeMap<String, Totals> = BTreeMap::new();
+    for entry in &entries {
+        per_day.entry(utc_date(entry.timestamp)).or_default().add(entry);
+        per_model.entry(entry.model.clone()).or_default().add(entry);
+    }
+
+    print_table("Per day (UTC)", &per_day);
+    println!();
+    print_table("Per model", &per_model);
+    if per_day.values().any(|totals| totals.unpriced && totals.cost_usd.is_some()) {
+        println!("{}", "+ also includes requests of unknown cost, which aren't counted".dimmed());
+    }
+    Ok(())
+}
+
+fn print_table(title: &str, rows: &BTreeMap<String, Totals>) {
+    let width = rows.keys().map(|key| key.chars().count()).max().unwrap_or(0).max(title.len());
+    println!(
+        "{}",
+        format!("{:<width$} {:>8} {:>12} {:>12} {:>12} {:>12}", title, "requests", "prompt", "completion", "total", "cost", width = width).cyan().bold(),
+    );
+    for (key, totals) in rows {
+        let cost = match (totals.cost_usd, totals.unpriced) {
+            (Some(cost), false) => format!("${:.4}", cost),
+            (Some(cost), true) => format!("${:.4}+", cost),
+            (None, _) => "-".to_string(),
+        };
+        println!(
+            "{:<width$} {:>8} {:>12} {:>12} {:>12} {:>12}",
+            key, totals.requests, totals.usage.prompt_tokens, totals.usage.completion_tokens, totals.usage.total_tokens, cost,
+            width = width,
+        );
+    }
+}
+
+/// `YYYY-MM-DD` of a Unix timestamp, in UTC.
+pub fn utc_date(timestamp: u64) -> String {
+    let (year, month, day) = civil_from_days((timestamp / 86_400) as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Converts days since 1970-01-01 into a proleptic Gregorian date
+/// (Howard Hinnant's `civil_from_days`).
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}